@@ -0,0 +1,360 @@
+//! Axis-aligned box collisions, kept free of ECS types so the maths can be reasoned about on its own.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+// Boxes closer than this are touching, not overlapping
+const EPSILON: f32 = 0.001;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub center: Vec2,
+    pub half_size: Vec2,
+}
+
+impl Aabb {
+    pub fn new(center: Vec2, size: Vec2) -> Self {
+        Self {
+            center,
+            half_size: size / 2.0,
+        }
+    }
+
+    pub fn min(&self) -> Vec2 {
+        self.center - self.half_size
+    }
+
+    pub fn max(&self) -> Vec2 {
+        self.center + self.half_size
+    }
+
+    /// Box covering the whole way from here to `motion` away
+    pub fn swept(&self, motion: Vec2) -> Aabb {
+        let min = self.min().min(self.min() + motion);
+        let max = self.max().max(self.max() + motion);
+        Aabb {
+            center: (min + max) / 2.0,
+            half_size: (max - min) / 2.0,
+        }
+    }
+
+    /// Whether the boxes share some area, touching edges don't count
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        overlaps_on(self, other, Axis::X) && overlaps_on(self, other, Axis::Y)
+    }
+
+    /// Smallest move that gets `self` out of `other`, if they overlap.
+    ///
+    /// Pushes along the axis with the least penetration, and up when the
+    /// centers are on top of each other.
+    pub fn penetration(&self, other: &Aabb) -> Option<Vec2> {
+        if !self.overlaps(other) {
+            return None;
+        }
+
+        let diff = self.center - other.center;
+        let depth = self.half_size + other.half_size - diff.abs();
+        let sign = |d: f32| if d < 0.0 { -1.0 } else { 1.0 };
+
+        Some(if depth.x < depth.y {
+            Vec2::new(depth.x * sign(diff.x), 0.0)
+        } else {
+            Vec2::new(0.0, depth.y * sign(diff.y))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+impl Axis {
+    fn of(self, v: Vec2) -> f32 {
+        match self {
+            Axis::X => v.x,
+            Axis::Y => v.y,
+        }
+    }
+
+    fn other(self) -> Axis {
+        match self {
+            Axis::X => Axis::Y,
+            Axis::Y => Axis::X,
+        }
+    }
+}
+
+fn overlaps_on(a: &Aabb, b: &Aabb, axis: Axis) -> bool {
+    axis.of(a.min()) < axis.of(b.max()) - EPSILON && axis.of(b.min()) < axis.of(a.max()) - EPSILON
+}
+
+/// How much of `distance` `aabb` can travel along `axis` before hitting `other`.
+///
+/// Boxes already overlapping don't block, that's for [`Aabb::penetration`] to sort out.
+pub fn sweep(aabb: &Aabb, axis: Axis, distance: f32, other: &Aabb) -> f32 {
+    if !overlaps_on(aabb, other, axis.other()) {
+        return distance;
+    }
+
+    if distance > 0.0 {
+        let gap = axis.of(other.min()) - axis.of(aabb.max());
+        if gap >= -EPSILON {
+            return distance.min(gap.max(0.0));
+        }
+    } else if distance < 0.0 {
+        let gap = axis.of(aabb.min()) - axis.of(other.max());
+        if gap >= -EPSILON {
+            return distance.max(-gap.max(0.0));
+        }
+    }
+    distance
+}
+
+/// Where a moving box ended up, and what it bumped into on the way
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contact {
+    pub position: Vec2,
+    /// Stopped by a wall while moving sideways
+    pub hit_wall: bool,
+    /// Stopped while moving up
+    pub hit_ceiling: bool,
+    /// Standing on something
+    pub on_ground: bool,
+}
+
+/// Moves `aabb` by `motion` through `solids`, one axis after the other so it
+/// slides along surfaces and can't tunnel through thin platforms.
+///
+/// `platforms` are one-way: they only stop a box falling onto them from above.
+pub fn move_and_collide(aabb: Aabb, motion: Vec2, solids: &[Aabb], platforms: &[Aabb]) -> Contact {
+    let mut aabb = aabb;
+    let mut contact = Contact {
+        position: aabb.center,
+        hit_wall: false,
+        hit_ceiling: false,
+        on_ground: false,
+    };
+
+    let dx = solids
+        .iter()
+        .fold(motion.x, |dx, solid| sweep(&aabb, Axis::X, dx, solid));
+    contact.hit_wall = dx != motion.x;
+    aabb.center.x += dx;
+
+    let dy = solids
+        .iter()
+        .fold(motion.y, |dy, solid| sweep(&aabb, Axis::Y, dy, solid));
+    // Platforms only stop a fall, and sweeping ignores boxes that start below their top
+    let dy = platforms.iter().fold(dy, |dy, platform| {
+        if dy < 0.0 {
+            sweep(&aabb, Axis::Y, dy, platform)
+        } else {
+            dy
+        }
+    });
+    if dy != motion.y {
+        contact.on_ground = motion.y < 0.0;
+        contact.hit_ceiling = motion.y > 0.0;
+    }
+    aabb.center.y += dy;
+
+    // Started inside something (spawned there, or pushed in): get out the shortest way
+    for solid in solids {
+        if let Some(push) = aabb.penetration(solid) {
+            aabb.center += push;
+            contact.on_ground |= push.y > 0.0;
+            contact.hit_ceiling |= push.y < 0.0;
+            contact.hit_wall |= push.x != 0.0;
+        }
+    }
+
+    contact.position = aabb.center;
+    contact
+}
+
+/// Broadphase: buckets boxes by grid cell so only nearby ones get tested
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<usize>>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cells(&self, aabb: &Aabb) -> impl Iterator<Item = IVec2> {
+        let min = (aabb.min() / self.cell_size).floor().as_ivec2();
+        let max = (aabb.max() / self.cell_size).floor().as_ivec2();
+        (min.x..=max.x).flat_map(move |x| (min.y..=max.y).map(move |y| IVec2::new(x, y)))
+    }
+
+    pub fn insert(&mut self, index: usize, aabb: &Aabb) {
+        for cell in self.cells(aabb).collect::<Vec<_>>() {
+            self.cells.entry(cell).or_default().push(index);
+        }
+    }
+
+    /// Indices of the boxes sharing a cell with `aabb`, each listed once
+    pub fn query(&self, aabb: &Aabb) -> Vec<usize> {
+        let mut found: Vec<usize> = self
+            .cells(aabb)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect();
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boxed(x: f32, y: f32, w: f32, h: f32) -> Aabb {
+        Aabb::new(Vec2::new(x, y), Vec2::new(w, h))
+    }
+
+    #[test]
+    fn sweep_stops_at_a_touching_edge() {
+        let aabb = boxed(0.0, 0.0, 10.0, 10.0);
+        let wall = boxed(10.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(sweep(&aabb, Axis::X, 5.0, &wall), 0.0);
+        // Moving away is fine
+        assert_eq!(sweep(&aabb, Axis::X, -5.0, &wall), -5.0);
+    }
+
+    #[test]
+    fn sweep_stops_at_the_gap() {
+        let aabb = boxed(0.0, 0.0, 10.0, 10.0);
+        let wall = boxed(0.0, 20.0, 10.0, 10.0);
+
+        assert_eq!(sweep(&aabb, Axis::Y, 50.0, &wall), 10.0);
+        assert_eq!(sweep(&aabb, Axis::Y, 4.0, &wall), 4.0);
+    }
+
+    #[test]
+    fn sweep_slides_past_a_corner() {
+        let aabb = boxed(0.0, 0.0, 10.0, 10.0);
+        // Only the corners touch, nothing is in the way along either axis
+        let corner = boxed(10.0, 10.0, 10.0, 10.0);
+
+        assert_eq!(sweep(&aabb, Axis::X, 5.0, &corner), 5.0);
+        assert_eq!(sweep(&aabb, Axis::Y, 5.0, &corner), 5.0);
+    }
+
+    #[test]
+    fn sweep_without_motion() {
+        let aabb = boxed(0.0, 0.0, 10.0, 10.0);
+        let wall = boxed(10.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(sweep(&aabb, Axis::X, 0.0, &wall), 0.0);
+        assert_eq!(sweep(&aabb, Axis::Y, 0.0, &wall), 0.0);
+    }
+
+    #[test]
+    fn diagonal_move_into_a_corner() {
+        let aabb = boxed(0.0, 0.0, 10.0, 10.0);
+        let solid = boxed(12.0, 12.0, 10.0, 10.0);
+
+        // Sideways first, which clears the solid, then up into its underside
+        let contact = move_and_collide(aabb, Vec2::new(5.0, 5.0), &[solid], &[]);
+        assert_eq!(contact.position, Vec2::new(5.0, 2.0));
+        assert!(contact.hit_ceiling);
+        assert!(!contact.hit_wall);
+        assert!(!contact.on_ground);
+    }
+
+    #[test]
+    fn lands_on_a_platform_from_above() {
+        let aabb = boxed(0.0, 20.0, 10.0, 10.0);
+        let platform = boxed(0.0, 0.0, 20.0, 10.0);
+
+        let contact = move_and_collide(aabb, Vec2::new(0.0, -20.0), &[], &[platform]);
+        assert_eq!(contact.position, Vec2::new(0.0, 10.0));
+        assert!(contact.on_ground);
+    }
+
+    #[test]
+    fn jumps_through_a_platform_from_below() {
+        let aabb = boxed(0.0, -20.0, 10.0, 10.0);
+        let platform = boxed(0.0, 0.0, 20.0, 10.0);
+
+        let contact = move_and_collide(aabb, Vec2::new(0.0, 30.0), &[], &[platform]);
+        assert_eq!(contact.position, Vec2::new(0.0, 10.0));
+        assert!(!contact.hit_ceiling);
+
+        // Halfway through on the way down, it doesn't catch either
+        let aabb = boxed(0.0, 3.0, 10.0, 10.0);
+        let contact = move_and_collide(aabb, Vec2::new(0.0, -5.0), &[], &[platform]);
+        assert_eq!(contact.position, Vec2::new(0.0, -2.0));
+        assert!(!contact.on_ground);
+    }
+
+    #[test]
+    fn platform_edge() {
+        let platform = boxed(0.0, 0.0, 20.0, 10.0);
+
+        // Just touching the end of the platform isn't standing on it
+        let aabb = boxed(15.0, 20.0, 10.0, 10.0);
+        let contact = move_and_collide(aabb, Vec2::new(0.0, -20.0), &[], &[platform]);
+        assert!(!contact.on_ground);
+        assert_eq!(contact.position, Vec2::new(15.0, 0.0));
+
+        // A sliver over it is
+        let aabb = boxed(14.9, 20.0, 10.0, 10.0);
+        let contact = move_and_collide(aabb, Vec2::new(0.0, -20.0), &[], &[platform]);
+        assert!(contact.on_ground);
+        assert_eq!(contact.position, Vec2::new(14.9, 10.0));
+    }
+
+    #[test]
+    fn pushed_out_of_a_solid_the_shortest_way() {
+        let solid = boxed(0.0, 0.0, 40.0, 20.0);
+
+        // Deep on the right side: out sideways
+        let aabb = boxed(18.0, 0.0, 10.0, 10.0);
+        let contact = move_and_collide(aabb, Vec2::ZERO, &[solid], &[]);
+        assert_eq!(contact.position, Vec2::new(25.0, 0.0));
+        assert!(contact.hit_wall);
+
+        // Sunk into the top: back up onto it
+        let aabb = boxed(0.0, 12.0, 10.0, 10.0);
+        let contact = move_and_collide(aabb, Vec2::ZERO, &[solid], &[]);
+        assert_eq!(contact.position, Vec2::new(0.0, 15.0));
+        assert!(contact.on_ground);
+    }
+
+    #[test]
+    fn pushed_up_when_centered_inside() {
+        let solid = boxed(0.0, 0.0, 20.0, 20.0);
+        let aabb = boxed(0.0, 0.0, 20.0, 20.0);
+
+        assert_eq!(aabb.penetration(&solid), Some(Vec2::new(0.0, 20.0)));
+        let contact = move_and_collide(aabb, Vec2::ZERO, &[solid], &[]);
+        assert_eq!(contact.position, Vec2::new(0.0, 20.0));
+        assert!(contact.on_ground);
+    }
+
+    #[test]
+    fn spatial_hash_finds_neighbours_once() {
+        let mut hash = SpatialHash::new(10.0);
+        hash.insert(0, &boxed(0.0, 0.0, 30.0, 30.0));
+        hash.insert(1, &boxed(100.0, 100.0, 5.0, 5.0));
+
+        assert_eq!(hash.query(&boxed(5.0, 5.0, 20.0, 20.0)), vec![0]);
+        assert_eq!(
+            hash.query(&boxed(-100.0, 0.0, 5.0, 5.0)),
+            Vec::<usize>::new()
+        );
+    }
+}
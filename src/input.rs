@@ -0,0 +1,325 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::Settings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Drop,
+    /// Only used when stations wait for a button press, see `Settings::interact_mode`
+    Interact,
+    /// Selects the next inventory slot
+    NextSlot,
+    /// Puts down what's in the selected slot
+    DropItem,
+    Pause,
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Drop,
+        Action::Interact,
+        Action::NextSlot,
+        Action::DropItem,
+        Action::Pause,
+    ];
+}
+
+// How far a stick has to be pushed to count as a press
+const STICK_THRESHOLD: f32 = 0.5;
+
+/// Keys (and gamepad buttons) each action can be rebound to in the controls menu
+pub const BINDING_SLOTS: usize = 3;
+
+/// Which keys and gamepad buttons trigger each action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bindings {
+    pub keys: HashMap<Action, Vec<KeyCode>>,
+    pub buttons: HashMap<Action, Vec<GamepadButtonType>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self {
+            keys: HashMap::from([
+                (Action::MoveLeft, vec![KeyCode::Left, KeyCode::A]),
+                (Action::MoveRight, vec![KeyCode::Right, KeyCode::D]),
+                (Action::Jump, vec![KeyCode::Up, KeyCode::W, KeyCode::Space]),
+                (Action::Drop, vec![KeyCode::Down, KeyCode::S]),
+                (Action::Interact, vec![KeyCode::E, KeyCode::Return]),
+                (Action::NextSlot, vec![KeyCode::Tab]),
+                (Action::DropItem, vec![KeyCode::Q]),
+                (Action::Pause, vec![KeyCode::Escape]),
+            ]),
+            buttons: HashMap::from([
+                (Action::MoveLeft, vec![GamepadButtonType::DPadLeft]),
+                (Action::MoveRight, vec![GamepadButtonType::DPadRight]),
+                (Action::Jump, vec![GamepadButtonType::South]),
+                (
+                    Action::Drop,
+                    vec![GamepadButtonType::DPadDown, GamepadButtonType::East],
+                ),
+                (Action::Interact, vec![GamepadButtonType::West]),
+                (Action::NextSlot, vec![GamepadButtonType::RightTrigger]),
+                (Action::DropItem, vec![GamepadButtonType::North]),
+                (Action::Pause, vec![GamepadButtonType::Start]),
+            ]),
+        }
+    }
+}
+
+impl Bindings {
+    fn names(&self, action: Action) -> impl Iterator<Item = String> + '_ {
+        let keys = self.keys.get(&action).into_iter().flatten();
+        let buttons = self.buttons.get(&action).into_iter().flatten();
+        keys.map(|key| format!("{key:?}"))
+            .chain(buttons.map(|button| format!("Pad {button:?}")))
+    }
+
+    /// Human readable list of what triggers `action`
+    pub fn describe(&self, action: Action) -> String {
+        self.names(action).collect::<Vec<_>>().join(", ")
+    }
+
+    /// Main key or button for `action`, to show in prompts
+    pub fn primary(&self, action: Action) -> String {
+        self.names(action).next().unwrap_or_else(|| "?".to_string())
+    }
+
+    /// Key and gamepad button in one slot of `action`
+    pub fn describe_slot(&self, action: Action, slot: usize) -> String {
+        let key = self.keys.get(&action).and_then(|keys| keys.get(slot));
+        let button = self
+            .buttons
+            .get(&action)
+            .and_then(|buttons| buttons.get(slot));
+        match (key, button) {
+            (Some(key), Some(button)) => format!("{key:?} / Pad {button:?}"),
+            (Some(key), None) => format!("{key:?}"),
+            (None, Some(button)) => format!("Pad {button:?}"),
+            (None, None) => "-".to_string(),
+        }
+    }
+
+    /// Puts `key` in `slot` of `action`, see [`rebind`]
+    pub fn bind_key(&mut self, action: Action, slot: usize, key: KeyCode) -> bool {
+        rebind(&mut self.keys, action, slot, key)
+    }
+
+    /// Puts `button` in `slot` of `action`, see [`rebind`]
+    pub fn bind_button(&mut self, action: Action, slot: usize, button: GamepadButtonType) -> bool {
+        rebind(&mut self.buttons, action, slot, button)
+    }
+
+    /// Binds actions added since the settings were saved, to defaults not already taken
+    pub fn add_missing(&mut self) {
+        let defaults = Bindings::default();
+        for (action, mut keys) in defaults.keys {
+            if !self.keys.contains_key(&action) {
+                keys.retain(|key| !self.keys.values().flatten().any(|used| used == key));
+                self.keys.insert(action, keys);
+            }
+        }
+        for (action, mut buttons) in defaults.buttons {
+            if !self.buttons.contains_key(&action) {
+                buttons
+                    .retain(|button| !self.buttons.values().flatten().any(|used| used == button));
+                self.buttons.insert(action, buttons);
+            }
+        }
+    }
+}
+
+/// Puts `input` in `slot` of `action`, keeping its other inputs.
+///
+/// An input only ever triggers one action: when another action has it, that
+/// action gets the input it replaces instead. Refused when there is none to
+/// give back and the other action would be left without any.
+fn rebind<T: Copy + PartialEq>(
+    inputs: &mut HashMap<Action, Vec<T>>,
+    action: Action,
+    slot: usize,
+    input: T,
+) -> bool {
+    let replaced = inputs.get(&action).and_then(|list| list.get(slot)).copied();
+    if replaced == Some(input) {
+        return true;
+    }
+
+    let taken = inputs.iter().find_map(|(&other, list)| {
+        let index = list.iter().position(|&used| used == input)?;
+        Some((other, index))
+    });
+    if let Some((other, index)) = taken {
+        let list = inputs.get_mut(&other).expect("Action was just found");
+        match replaced {
+            Some(replaced) => list[index] = replaced,
+            None if list.len() > 1 => {
+                list.remove(index);
+            }
+            None => return false,
+        }
+    }
+
+    let list = inputs.entry(action).or_default();
+    if slot < list.len() {
+        list[slot] = input;
+    } else {
+        list.push(input);
+    }
+    true
+}
+
+/// Actions held down this frame, whatever the device
+#[derive(Resource, Default)]
+pub struct ActionState {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    // Presses the fixed timestep hasn't seen yet, it can skip frames
+    fixed_just_pressed: HashSet<Action>,
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    /// Like `just_pressed`, for systems in `FixedUpdate`
+    pub fn fixed_just_pressed(&self, action: Action) -> bool {
+        self.fixed_just_pressed.contains(&action)
+    }
+}
+
+pub fn update_actions_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    settings: Res<Settings>,
+    mut state: ResMut<ActionState>,
+) {
+    let bindings = &settings.bindings;
+    let mut pressed = HashSet::new();
+
+    for action in Action::ALL {
+        let key = bindings
+            .keys
+            .get(&action)
+            .is_some_and(|keys| keyboard_input.any_pressed(keys.iter().copied()));
+
+        let button = bindings.buttons.get(&action).is_some_and(|buttons| {
+            gamepads.iter().any(|gamepad| {
+                gamepad_buttons.any_pressed(
+                    buttons
+                        .iter()
+                        .map(|&button_type| GamepadButton::new(gamepad, button_type)),
+                )
+            })
+        });
+
+        if key || button {
+            pressed.insert(action);
+        }
+    }
+
+    // Left stick moves and drops, like the D-pad
+    for gamepad in gamepads.iter() {
+        let x = gamepad_axes
+            .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+            .unwrap_or(0.0);
+        let y = gamepad_axes
+            .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY))
+            .unwrap_or(0.0);
+
+        if x < -STICK_THRESHOLD {
+            pressed.insert(Action::MoveLeft);
+        } else if x > STICK_THRESHOLD {
+            pressed.insert(Action::MoveRight);
+        }
+        if y < -STICK_THRESHOLD {
+            pressed.insert(Action::Drop);
+        }
+    }
+
+    state.just_pressed = pressed.difference(&state.pressed).copied().collect();
+    let just_pressed = state.just_pressed.clone();
+    state.fixed_just_pressed.extend(just_pressed);
+    state.pressed = pressed;
+}
+
+/// Runs after every fixed step, so a press is only handled once
+pub fn clear_fixed_actions_system(mut state: ResMut<ActionState>) {
+    state.fixed_just_pressed.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bindings: &Bindings, action: Action) -> Vec<KeyCode> {
+        bindings.keys.get(&action).cloned().unwrap_or_default()
+    }
+
+    #[test]
+    fn rebinding_a_slot_keeps_the_others() {
+        let mut bindings = Bindings::default();
+
+        assert!(bindings.bind_key(Action::Jump, 1, KeyCode::K));
+        assert_eq!(
+            keys(&bindings, Action::Jump),
+            vec![KeyCode::Up, KeyCode::K, KeyCode::Space]
+        );
+
+        // Past the end adds a key
+        assert!(bindings.bind_key(Action::Pause, 2, KeyCode::P));
+        assert_eq!(
+            keys(&bindings, Action::Pause),
+            vec![KeyCode::Escape, KeyCode::P]
+        );
+    }
+
+    #[test]
+    fn taken_key_is_swapped() {
+        let mut bindings = Bindings::default();
+
+        // D moves right, the left key it replaces goes there instead
+        assert!(bindings.bind_key(Action::MoveLeft, 0, KeyCode::D));
+        assert_eq!(
+            keys(&bindings, Action::MoveLeft),
+            vec![KeyCode::D, KeyCode::A]
+        );
+        assert_eq!(
+            keys(&bindings, Action::MoveRight),
+            vec![KeyCode::Right, KeyCode::Left]
+        );
+    }
+
+    #[test]
+    fn never_leaves_an_action_unbound() {
+        let mut bindings = Bindings::default();
+
+        // Nothing to give back to pause for its only key
+        assert!(!bindings.bind_key(Action::Jump, 3, KeyCode::Escape));
+        assert_eq!(keys(&bindings, Action::Pause), vec![KeyCode::Escape]);
+        assert_eq!(
+            keys(&bindings, Action::Jump),
+            vec![KeyCode::Up, KeyCode::W, KeyCode::Space]
+        );
+
+        // Drop has another key left
+        assert!(bindings.bind_key(Action::Jump, 3, KeyCode::S));
+        assert_eq!(keys(&bindings, Action::Drop), vec![KeyCode::Down]);
+    }
+}
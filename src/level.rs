@@ -0,0 +1,642 @@
+use std::{fmt, ops::RangeInclusive, str::FromStr, time::Duration};
+
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::{TypePath, TypeUuid},
+};
+use serde::Deserialize;
+
+use crate::{
+    ingredient_texture, recipe::StationType, Batch, BatchBar, Bin, CakeType, Collision,
+    CollisionBox, CookingTable, GameElement, IngredientType, OneWayPlatform, Stock, Teller,
+    TriggerBox, BATCH_BAR_SIZE,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum StationKind {
+    Teller,
+    Mixer,
+    /// Was the only cooking table before there were several kinds
+    #[serde(alias = "CookingTable")]
+    Oven,
+    DecoratingTable,
+    Bin,
+}
+
+impl StationKind {
+    /// Which recipe steps can be done here
+    pub fn station_type(self) -> Option<StationType> {
+        match self {
+            StationKind::Mixer => Some(StationType::Mixer),
+            StationKind::Oven => Some(StationType::Oven),
+            StationKind::DecoratingTable => Some(StationType::DecoratingTable),
+            StationKind::Teller | StationKind::Bin => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Solid {
+    pub position: Vec2,
+    pub size: Vec2,
+    /// Can be jumped through from below and dropped through from above
+    pub one_way: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Station {
+    pub kind: StationKind,
+    pub position: Vec2,
+}
+
+/// A cake customers can order, and how often compared to the others
+#[derive(Debug, Clone)]
+pub struct MenuItem {
+    pub cake: CakeType,
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Dispenser {
+    pub ingredient: IngredientType,
+    pub position: Vec2,
+    /// How many it holds at the start, until a delivery
+    pub stock: u32,
+}
+
+/// A kitchen layout, loaded from a `.level.ron` file
+#[derive(Debug, Clone, TypeUuid, TypePath)]
+#[uuid = "b7f0c2a4-3e91-4d5b-a6c8-1f2e3d4c5b6a"]
+pub struct Level {
+    pub name: String,
+    /// How long the shift lasts
+    pub time: Duration,
+    /// Cakes to sell for one, two and three stars, the first being the goal to clear the level
+    pub stars: [usize; 3],
+    pub solids: Vec<Solid>,
+    pub stations: Vec<Station>,
+    pub dispensers: Vec<Dispenser>,
+    /// How often dispensers get restocked
+    pub delivery: Duration,
+    /// Inventory slots, before upgrades
+    pub capacity: usize,
+    /// What customers order, every cake the kitchen can make when empty
+    pub menu: Vec<MenuItem>,
+    /// Cake ordered more often than the rest, picked at random each shift when not set
+    pub special: Option<CakeType>,
+    pub player: Vec2,
+    /// Front of the customer line, which grows to the left
+    pub customers: Vec2,
+}
+
+// What the designers write, before validation
+#[derive(Deserialize)]
+struct RawLevel {
+    name: String,
+    time: u64,
+    stars: [usize; 3],
+    solids: Vec<RawSolid>,
+    stations: Vec<RawStation>,
+    dispensers: Vec<RawDispenser>,
+    #[serde(default = "default_delivery")]
+    delivery: u64,
+    #[serde(default = "default_capacity")]
+    capacity: usize,
+    #[serde(default)]
+    menu: Vec<RawMenuItem>,
+    #[serde(default)]
+    special: Option<String>,
+    player: (f32, f32),
+    customers: (f32, f32),
+}
+
+#[derive(Deserialize)]
+struct RawSolid {
+    position: (f32, f32),
+    size: (f32, f32),
+    #[serde(default)]
+    one_way: bool,
+}
+
+#[derive(Deserialize)]
+struct RawStation {
+    kind: StationKind,
+    position: (f32, f32),
+}
+
+#[derive(Deserialize)]
+struct RawDispenser {
+    ingredient: String,
+    position: (f32, f32),
+    #[serde(default = "default_stock")]
+    stock: u32,
+}
+
+#[derive(Deserialize)]
+struct RawMenuItem {
+    cake: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_delivery() -> u64 {
+    60
+}
+
+fn default_stock() -> u32 {
+    6
+}
+
+fn default_capacity() -> usize {
+    4
+}
+
+/// How many inventory slots a level can give
+pub const CAPACITY: RangeInclusive<usize> = 1..=6;
+
+#[derive(Debug)]
+pub enum LevelError {
+    Parse(ron::error::SpannedError),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelError::Parse(err) => write!(f, "could not parse level: {err}"),
+            LevelError::Invalid(problems) => {
+                write!(f, "invalid level:")?;
+                for problem in problems {
+                    write!(f, "\n  - {problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl Level {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LevelError> {
+        let raw: RawLevel = ron::de::from_bytes(bytes).map_err(LevelError::Parse)?;
+
+        let mut problems = Vec::new();
+
+        if raw.time == 0 {
+            problems.push("time must be positive".to_string());
+        }
+        if raw.delivery == 0 {
+            problems.push("delivery must be positive".to_string());
+        }
+        if !CAPACITY.contains(&raw.capacity) {
+            problems.push(format!(
+                "capacity must be {} to {}, got {}",
+                CAPACITY.start(),
+                CAPACITY.end(),
+                raw.capacity
+            ));
+        }
+        if raw.stars[0] == 0 || raw.stars.windows(2).any(|pair| pair[0] >= pair[1]) {
+            problems.push(format!(
+                "stars must be increasing and positive, got {:?}",
+                raw.stars
+            ));
+        }
+
+        for (index, solid) in raw.solids.iter().enumerate() {
+            if solid.size.0 <= 0.0 || solid.size.1 <= 0.0 {
+                problems.push(format!("solid #{}: size must be positive", index + 1));
+            }
+        }
+
+        if !raw
+            .stations
+            .iter()
+            .any(|station| station.kind == StationKind::Teller)
+        {
+            problems.push("no Teller station".to_string());
+        }
+        if !raw
+            .stations
+            .iter()
+            .any(|station| station.kind.station_type().is_some())
+        {
+            problems.push("no cooking station".to_string());
+        }
+
+        for (index, item) in raw.menu.iter().enumerate() {
+            if item.weight == 0 {
+                problems.push(format!("menu item #{}: weight must be positive", index + 1));
+            }
+        }
+
+        let mut dispensers = Vec::new();
+        for (index, dispenser) in raw.dispensers.iter().enumerate() {
+            match IngredientType::from_str(&dispenser.ingredient) {
+                Ok(ingredient) => dispensers.push(Dispenser {
+                    ingredient,
+                    position: dispenser.position.into(),
+                    stock: dispenser.stock,
+                }),
+                Err(_) => problems.push(format!(
+                    "dispenser #{}: unknown ingredient {:?}",
+                    index + 1,
+                    dispenser.ingredient
+                )),
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(LevelError::Invalid(problems));
+        }
+
+        Ok(Self {
+            name: raw.name,
+            time: Duration::from_secs(raw.time),
+            stars: raw.stars,
+            solids: raw
+                .solids
+                .into_iter()
+                .map(|solid| Solid {
+                    position: solid.position.into(),
+                    size: solid.size.into(),
+                    one_way: solid.one_way,
+                })
+                .collect(),
+            stations: raw
+                .stations
+                .into_iter()
+                .map(|station| Station {
+                    kind: station.kind,
+                    position: station.position.into(),
+                })
+                .collect(),
+            dispensers,
+            delivery: Duration::from_secs(raw.delivery),
+            capacity: raw.capacity,
+            menu: raw
+                .menu
+                .into_iter()
+                .map(|item| MenuItem {
+                    cake: CakeType(item.cake),
+                    weight: item.weight,
+                })
+                .collect(),
+            special: raw.special.map(CakeType),
+            player: raw.player.into(),
+            customers: raw.customers.into(),
+        })
+    }
+
+    /// Cakes to sell to clear the level
+    pub fn goal(&self) -> usize {
+        self.stars[0]
+    }
+
+    /// Whether every step of a recipe can be done in this kitchen
+    pub fn can_make(&self, steps: &[StationType]) -> bool {
+        steps.iter().all(|&step| {
+            self.stations
+                .iter()
+                .any(|station| station.kind.station_type() == Some(step))
+        })
+    }
+
+    pub fn stars_for(&self, score: usize) -> u8 {
+        self.stars.iter().filter(|&&needed| score >= needed).count() as u8
+    }
+
+    /// Where the customer at `index` in line stands
+    pub fn customer_slot(&self, index: usize) -> Vec3 {
+        Vec3::new(
+            self.customers.x - 70.0 * index as f32,
+            self.customers.y,
+            0.0,
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let level = Level::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+/// Spawns the static part of a level: solids, stations and dispensers
+pub fn spawn_level(asset_server: &Res<AssetServer>, commands: &mut Commands, level: &Level) {
+    for Solid {
+        position,
+        size,
+        one_way,
+    } in level.solids.iter()
+    {
+        let mut solid = commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::MAROON,
+                    custom_size: Some(*size),
+                    ..default()
+                },
+                transform: Transform::from_translation(position.extend(0.0)),
+                ..default()
+            },
+            Collision,
+            CollisionBox(size.extend(0.0)),
+            GameElement,
+        ));
+        if *one_way {
+            solid.insert(OneWayPlatform);
+        }
+    }
+
+    for Station { kind, position } in level.stations.iter() {
+        match kind {
+            StationKind::Teller => {
+                commands.spawn((
+                    SpriteBundle {
+                        texture: asset_server.load("sprites/teller.png"),
+                        transform: Transform::from_translation(position.extend(0.0))
+                            .with_scale(Vec3::new(0.6, 0.6, 0.0)),
+                        ..default()
+                    },
+                    Collision,
+                    CollisionBox(Vec3::new(48.0, 70.0, 0.0)),
+                    Teller,
+                    TriggerBox(Vec3::new(60.0, 72.0, 0.0)),
+                    GameElement,
+                ));
+            }
+            StationKind::Mixer | StationKind::Oven | StationKind::DecoratingTable => {
+                let station = kind.station_type().expect("Cooking station");
+                spawn_cooking_table(asset_server, commands, *position, station);
+            }
+            StationKind::Bin => {
+                commands.spawn((
+                    SpriteBundle {
+                        texture: asset_server.load("sprites/bin.png"),
+                        transform: Transform::from_translation(position.extend(-10.0))
+                            .with_scale(Vec3::new(0.6, 0.6, 0.0)),
+                        ..default()
+                    },
+                    Bin,
+                    TriggerBox(Vec3::new(50.0, 40.0, 0.0)),
+                    GameElement,
+                ));
+            }
+        }
+    }
+
+    for Dispenser {
+        ingredient,
+        position,
+        stock,
+    } in level.dispensers.iter()
+    {
+        commands
+            .spawn((
+                SpriteBundle {
+                    texture: ingredient_texture(asset_server, ingredient),
+                    transform: Transform::from_translation(position.extend(0.0))
+                        .with_scale(Vec3::new(0.8, 0.8, 0.0)),
+                    ..default()
+                },
+                Stock {
+                    ingredient: ingredient.clone(),
+                    count: *stock,
+                },
+                TriggerBox(Vec3::new(40.0, 40.0, 0.0)),
+                GameElement,
+            ))
+            .with_children(|parent| {
+                // Stock left, just above
+                parent.spawn((
+                    Text2dBundle {
+                        text: Text::from_section(
+                            format!("x{stock}"),
+                            TextStyle {
+                                font_size: 20.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        ),
+                        transform: Transform::from_xyz(0.0, 40.0, 5.0)
+                            .with_scale(Vec3::new(1.25, 1.25, 1.0)),
+                        ..default()
+                    },
+                    GameElement,
+                ));
+            });
+    }
+}
+
+fn spawn_cooking_table(
+    asset_server: &Res<AssetServer>,
+    commands: &mut Commands,
+    position: Vec2,
+    station: StationType,
+) {
+    // Same machine for everything, smaller and tinted for the other stations
+    let (scale, color) = match station {
+        StationType::Oven => (0.6, Color::WHITE),
+        StationType::Mixer => (0.3, Color::rgb(0.6, 0.8, 1.0)),
+        StationType::DecoratingTable => (0.3, Color::rgb(1.0, 0.7, 0.85)),
+    };
+    let size = Vec2::new(368.0, 369.0);
+
+    commands
+        .spawn((
+            SpriteBundle {
+                sprite: Sprite { color, ..default() },
+                texture: asset_server.load("sprites/cake_machine.png"),
+                transform: Transform::from_translation(position.extend(0.0))
+                    .with_scale(Vec3::new(scale, scale, 0.0)),
+                ..default()
+            },
+            Collision,
+            CollisionBox((size * scale).extend(0.0)),
+            CookingTable(station),
+            Batch::default(),
+            TriggerBox((size * (scale + 0.05)).extend(0.0)),
+            GameElement,
+        ))
+        .with_children(|parent| {
+            // Undo the machine's scale, so children are sized in pixels
+            let unscaled = Vec3::new(1.0 / scale, 1.0 / scale, 1.0);
+
+            // Progress of what's inside, just above the machine
+            parent.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        custom_size: Some(BATCH_BAR_SIZE),
+                        ..default()
+                    },
+                    transform: Transform::from_xyz(0.0, size.y / 2.0 + 20.0 / scale, 1.0)
+                        .with_scale(unscaled),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                BatchBar,
+                GameElement,
+            ));
+
+            if station != StationType::Oven {
+                parent.spawn((
+                    Text2dBundle {
+                        text: Text::from_section(
+                            station.name(),
+                            TextStyle {
+                                font_size: 20.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        ),
+                        transform: Transform::from_xyz(0.0, 0.0, 1.0).with_scale(unscaled),
+                        ..default()
+                    },
+                    GameElement,
+                ));
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+    use crate::recipe::RecipeBook;
+
+    fn asset(path: &str) -> Vec<u8> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(path);
+        fs::read(&path).unwrap_or_else(|err| panic!("can't read {}: {err}", path.display()))
+    }
+
+    fn problems(ron: &str) -> Vec<String> {
+        match Level::from_bytes(ron.as_bytes()) {
+            Err(LevelError::Invalid(problems)) => problems,
+            Err(err) => panic!("expected validation problems, got {err}"),
+            Ok(_) => panic!("expected validation problems, got a valid level"),
+        }
+    }
+
+    #[test]
+    fn shipped_levels_load() {
+        let book = RecipeBook::from_bytes(&asset("bakery.recipes.ron"))
+            .unwrap_or_else(|err| panic!("bakery.recipes.ron: {err}"));
+
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/levels");
+        let mut count = 0;
+        for entry in fs::read_dir(dir).expect("Levels directory") {
+            let path = entry.expect("Level file").path();
+            let name = path.display();
+            let level = Level::from_bytes(&fs::read(&path).expect("Level file"))
+                .unwrap_or_else(|err| panic!("{name}: {err}"));
+
+            // Everything on the menu is in the book and can be made in this kitchen
+            for item in &level.menu {
+                let recipe = book
+                    .cake(&item.cake)
+                    .unwrap_or_else(|| panic!("{name}: unknown cake {:?}", item.cake));
+                assert!(
+                    level.can_make(&book.stations_for(recipe)),
+                    "{name}: {:?} can't be made",
+                    item.cake
+                );
+            }
+            if let Some(special) = &level.special {
+                assert!(book.cake(special).is_some(), "{name}: unknown special");
+            }
+            assert!(
+                book.cakes
+                    .iter()
+                    .any(|recipe| level.can_make(&book.stations_for(recipe))),
+                "{name}: no cake can be made"
+            );
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn defaults() {
+        let level = Level::from_bytes(
+            br#"(
+                name: "Tiny",
+                time: 60,
+                stars: (1, 2, 3),
+                solids: [],
+                stations: [(kind: Teller, position: (0, 0)), (kind: CookingTable, position: (1, 0))],
+                dispensers: [(ingredient: "Eggs", position: (2, 0))],
+                player: (0, 0),
+                customers: (0, 0),
+            )"#,
+        )
+        .expect("Minimal level is valid");
+
+        assert_eq!(level.stations[1].kind, StationKind::Oven);
+        assert_eq!(level.dispensers[0].stock, default_stock());
+        assert_eq!(level.delivery, Duration::from_secs(default_delivery()));
+        assert_eq!(level.capacity, default_capacity());
+        assert!(level.menu.is_empty());
+        assert_eq!(level.special, None);
+    }
+
+    #[test]
+    fn validation_lists_every_problem() {
+        let problems = problems(
+            r#"(
+                name: "Broken",
+                time: 0,
+                stars: (5, 5, 3),
+                solids: [(position: (0, 0), size: (10, 0))],
+                stations: [(kind: Oven, position: (0, 0))],
+                dispensers: [
+                    (ingredient: "Eggs", position: (0, 0)),
+                    (ingredient: "Butter", position: (0, 0)),
+                ],
+                delivery: 0,
+                capacity: 9,
+                menu: [(cake: "chocolate", weight: 0)],
+                player: (0, 0),
+                customers: (0, 0),
+            )"#,
+        );
+
+        assert_eq!(
+            problems,
+            vec![
+                "time must be positive",
+                "delivery must be positive",
+                "capacity must be 1 to 6, got 9",
+                "stars must be increasing and positive, got [5, 5, 3]",
+                "solid #1: size must be positive",
+                "no Teller station",
+                "menu item #1: weight must be positive",
+                r#"dispenser #2: unknown ingredient "Butter""#,
+            ]
+        );
+    }
+}
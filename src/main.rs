@@ -1,10 +1,28 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::Duration,
+};
 
 use bevy::{
-    app::AppExit, audio::PlaybackMode, prelude::*, sprite::collide_aabb::collide, utils::HashMap,
+    app::AppExit, audio::PlaybackMode, ecs::system::SystemParam, prelude::*,
+    window::ReceivedCharacter,
 };
 
-use rand::{distributions::Standard, prelude::Distribution, Rng};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+mod collision;
+mod input;
+mod level;
+mod recipe;
+mod save;
+
+use collision::{Aabb, SpatialHash};
+use input::{Action, ActionState, Bindings, BINDING_SLOTS};
+use level::{Level, LevelLoader};
+use recipe::{CakeRecipe, Craft, RecipeBook, RecipeBookLoader, StationType};
 
 #[derive(Component)]
 struct CollisionBox(Vec3);
@@ -15,6 +33,10 @@ struct TriggerBox(Vec3);
 #[derive(Resource)]
 struct Score(usize);
 
+/// Customers who gave up waiting
+#[derive(Resource)]
+struct MissedCustomers(usize);
+
 #[derive(Resource)]
 struct LevelTimer(Timer);
 
@@ -22,10 +44,22 @@ struct LevelTimer(Timer);
 enum GameState {
     #[default]
     MainMenu,
+    LevelSelect,
+    Controls,
     InGame,
+    /// Goes straight back to `InGame`, so the level is set up again
+    Restarting,
     EndScreen,
 }
 
+/// Only meaningful while `InGame`
+#[derive(Default, States, Clone, PartialEq, Eq, Debug, Hash)]
+enum PauseState {
+    #[default]
+    Running,
+    Paused,
+}
+
 fn despawn_all<T: Component>(to_despawn: Query<Entity, With<T>>, mut commands: Commands) {
     info!(
         "despawn all {:#?} ({})",
@@ -40,49 +74,163 @@ fn despawn_all<T: Component>(to_despawn: Query<Entity, With<T>>, mut commands: C
 fn main() {
     App::new()
         .add_state::<GameState>()
-        .add_event::<OnGroundEvent>()
-        .insert_resource(Recipes(HashMap::new()))
+        .add_state::<PauseState>()
+        .add_event::<TriggerEnter>()
+        .add_event::<TriggerStay>()
+        .add_event::<TriggerExit>()
+        .init_resource::<Recipes>()
+        .init_resource::<SelectedLevel>()
+        .insert_resource(save::load::<Campaign>(CAMPAIGN_SAVE))
+        .insert_resource(Settings::load())
+        .init_resource::<ActionState>()
+        .init_resource::<Rebinding>()
+        .init_resource::<SeedEntry>()
+        .init_resource::<PlatformerController>()
+        .init_resource::<TriggerContacts>()
+        .init_resource::<InteractFocus>()
         .insert_resource(Score(0))
+        .insert_resource(MissedCustomers(0))
+        .insert_resource(FixedTime::new_from_secs(PHYSICS_STEP))
         /* General systems */
         .add_systems(Startup, setup)
-        .add_systems(Update, (button_system, bevy::window::close_on_esc))
+        .add_systems(Update, (button_system, load_recipes_system))
         /* Main menu */
         .add_plugins(DefaultPlugins)
+        .add_asset::<RecipeBook>()
+        .init_asset_loader::<RecipeBookLoader>()
+        .add_asset::<Level>()
+        .init_asset_loader::<LevelLoader>()
+        .add_systems(
+            PreUpdate,
+            input::update_actions_system.after(bevy::input::InputSystem),
+        )
         .add_systems(OnEnter(GameState::MainMenu), setup_title_menu)
         .add_systems(
             Update,
             (title_menu, title_menu_action).run_if(in_state(GameState::MainMenu)),
         )
         .add_systems(OnExit(GameState::MainMenu), despawn_all::<TitleMenu>)
+        /* Level select */
+        .add_systems(OnEnter(GameState::LevelSelect), setup_level_select)
+        .add_systems(
+            Update,
+            (update_level_buttons, seed_entry_system, update_seed_text)
+                .chain()
+                .run_if(in_state(GameState::LevelSelect)),
+        )
+        .add_systems(
+            OnExit(GameState::LevelSelect),
+            despawn_all::<LevelSelectMenu>,
+        )
+        /* Controls */
+        .add_systems(OnEnter(GameState::Controls), setup_controls_menu)
+        .add_systems(
+            Update,
+            (rebind_system, update_binding_text).run_if(in_state(GameState::Controls)),
+        )
+        .add_systems(OnExit(GameState::Controls), despawn_all::<ControlsMenu>)
         /* In game systems */
         .add_systems(OnEnter(GameState::InGame), setup_game)
         .add_systems(
-            Update,
+            FixedUpdate,
             (
-                movement_system,
+                restore_physics_transform_system,
+                customer_movement_system,
                 jump_system,
+                movement_system,
+                drop_through_system,
                 gravity_system,
                 physics_system,
                 collision_system,
+                record_physics_transform_system,
+            )
+                .chain()
+                .run_if(in_state(GameState::InGame))
+                .run_if(in_state(PauseState::Running)),
+        )
+        .add_systems(
+            FixedUpdate,
+            input::clear_fixed_actions_system.after(record_physics_transform_system),
+        )
+        .add_systems(
+            PostUpdate,
+            interpolate_transform_system
+                .before(bevy::transform::TransformSystem::TransformPropagate)
+                .run_if(in_state(GameState::InGame))
+                .run_if(in_state(PauseState::Running)),
+        )
+        .add_systems(
+            Update,
+            (
+                trigger_system,
+                interact_focus_system,
+                interact_prompt_system,
             )
                 .chain()
-                .run_if(in_state(GameState::InGame)),
+                .run_if(in_state(GameState::InGame))
+                .run_if(in_state(PauseState::Running)),
         )
         .add_systems(
             Update,
             (
                 level_timer_system,
-                trigger_ingredient_system,
+                pickup_system,
+                trigger_highlight_system,
                 teller_system,
+                customer_arrival_system,
+                patience_system,
+                patience_bar_system,
                 cooking_table_system,
+                batch_system,
+                batch_bar_system,
                 bin_system,
+                dispenser_system,
+                delivery_system,
+                stock_text_system,
                 update_score_ui,
                 update_timer_ui,
             )
-                .run_if(in_state(GameState::InGame)),
+                .after(interact_focus_system)
+                .run_if(in_state(GameState::InGame))
+                .run_if(in_state(PauseState::Running)),
+        )
+        .add_systems(
+            Update,
+            (
+                select_slot_system,
+                drop_item_system,
+                just_dropped_system,
+                update_inventory_ui,
+                carried_layout_system,
+            )
+                .after(interact_focus_system)
+                .run_if(in_state(GameState::InGame))
+                .run_if(in_state(PauseState::Running)),
+        )
+        .add_systems(Update, pause_system.run_if(in_state(GameState::InGame)))
+        /* Pause menu */
+        .add_systems(OnEnter(PauseState::Paused), setup_pause_menu)
+        .add_systems(OnExit(PauseState::Paused), despawn_all::<PauseMenu>)
+        .add_systems(
+            OnEnter(GameState::Restarting),
+            (
+                despawn_all::<EndScreen>,
+                despawn_all::<GameElement>,
+                restart_system,
+            ),
+        )
+        .add_systems(
+            OnTransition {
+                from: GameState::InGame,
+                to: GameState::MainMenu,
+            },
+            (despawn_all::<EndScreen>, despawn_all::<GameElement>),
         )
         /* End screen */
-        .add_systems(OnEnter(GameState::EndScreen), setup_end_screen)
+        .add_systems(
+            OnEnter(GameState::EndScreen),
+            update_campaign.pipe(setup_end_screen),
+        )
         .add_systems(Update, title_menu_action)
         .add_systems(
             OnExit(GameState::EndScreen),
@@ -95,6 +243,7 @@ fn main() {
 #[derive(Component)]
 struct NPC {
     wants: CakeType,
+    patience: Timer,
 }
 
 #[derive(Component)]
@@ -103,6 +252,10 @@ struct Player;
 #[derive(Component)]
 struct Collision;
 
+/// Only solid from above, see [`collision::move_and_collide`]
+#[derive(Component)]
+struct OneWayPlatform;
+
 #[derive(Component)]
 struct Velocity(Vec3);
 
@@ -120,6 +273,88 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         },
         ..default()
     });
+
+    commands.insert_resource(RecipeBookHandle(asset_server.load("bakery.recipes.ron")));
+
+    commands.insert_resource(LevelHandles(
+        LEVELS.iter().map(|path| asset_server.load(*path)).collect(),
+    ));
+
+    let seed = Seed::from_args();
+    info!("Seed: {}", seed.0);
+    commands.insert_resource(seed);
+    commands.insert_resource(GameRng::new(seed));
+    commands.insert_resource(DailySeed(Seed::daily()));
+}
+
+const LEVELS: &[&str] = &[
+    "levels/bakery.level.ron",
+    "levels/corner_shop.level.ron",
+    "levels/mezzanine.level.ron",
+];
+
+#[derive(Resource)]
+struct LevelHandles(Vec<Handle<Level>>);
+
+/// Index in [`LEVELS`] of the level to play
+#[derive(Resource, Default)]
+struct SelectedLevel(usize);
+
+/// The level being played
+#[derive(Resource)]
+struct CurrentLevel(Level);
+
+impl LevelHandles {
+    fn get<'a>(&self, levels: &'a Assets<Level>, index: usize) -> Option<&'a Level> {
+        self.0.get(index).and_then(|handle| levels.get(handle))
+    }
+}
+
+/// Which level the next shift is played in, and with what seed
+#[derive(SystemParam)]
+struct NextShift<'w> {
+    recipes: Res<'w, Recipes>,
+    levels: Res<'w, Assets<Level>>,
+    level_handles: Res<'w, LevelHandles>,
+    campaign: Res<'w, Campaign>,
+    selected: ResMut<'w, SelectedLevel>,
+    seed: ResMut<'w, Seed>,
+    daily: Res<'w, DailySeed>,
+    seed_entry: ResMut<'w, SeedEntry>,
+}
+
+impl NextShift<'_> {
+    fn level(&self) -> Option<&Level> {
+        self.level_handles.get(&self.levels, self.selected.0)
+    }
+
+    /// Switches to the seed being typed in, if it's a number
+    fn finish_seed_entry(&mut self) {
+        let Some(digits) = self.seed_entry.0.take() else {
+            return;
+        };
+        if let Ok(seed) = digits.parse() {
+            *self.seed = Seed(seed);
+        }
+    }
+
+    /// Selects `level` if everything it needs is loaded and it is unlocked
+    fn select(&mut self, level: usize) -> bool {
+        let loaded = self.level_handles.get(&self.levels, level);
+        if self.recipes.0.cakes.is_empty() {
+            warn!("Recipe book is not loaded, can't start a game")
+        } else if loaded.is_none() {
+            warn!("Level {level} is not loaded, can't start a game")
+        } else if loaded.is_some_and(|loaded| !Menu::has_orders(loaded, &self.recipes)) {
+            warn!("Level {level} has nothing on its menu the kitchen can make")
+        } else if !self.campaign.is_unlocked(level) {
+            warn!("Level {level} is locked")
+        } else {
+            self.selected.0 = level;
+            return true;
+        }
+        false
+    }
 }
 
 #[derive(Component)]
@@ -127,82 +362,51 @@ struct GameElement;
 
 fn setup_game(
     mut commands: Commands,
-    mut recipes: ResMut<Recipes>,
+    shift: NextShift,
     mut score: ResMut<Score>,
+    mut missed: ResMut<MissedCustomers>,
+    mut rng: ResMut<GameRng>,
     asset_server: Res<AssetServer>,
 ) {
+    let recipes = &shift.recipes;
     score.0 = 0;
+    missed.0 = 0;
+    // Same seed, same shift: restarting plays it again
+    *rng = GameRng::new(*shift.seed);
+    commands.insert_resource(TriggerContacts::default());
 
-    commands.insert_resource(LevelTimer(Timer::new(
-        Duration::from_secs(60 * 5),
-        TimerMode::Once,
-    )));
-
-    let r = vec![
-        (
-            CakeType::Chocolate,
-            Recipe::new(&[
-                IngredientType::Eggs,
-                IngredientType::Flour,
-                IngredientType::Chocolate,
-                IngredientType::Milk,
-            ]),
-        ),
-        (
-            CakeType::Fraisier,
-            Recipe::new(&[
-                IngredientType::Eggs,
-                IngredientType::Flour,
-                IngredientType::Strawberry,
-                IngredientType::Milk,
-            ]),
-        ),
-        (
-            CakeType::Carrot,
-            Recipe::new(&[
-                IngredientType::Eggs,
-                IngredientType::Flour,
-                IngredientType::Carrot,
-                IngredientType::Milk,
-            ]),
-        ),
-    ];
-
-    for (cake, ings) in r {
-        info!("Adding {:?} with {:?}", cake, ings);
-        recipes.0.insert(ings, cake);
-    }
-
-    // Floor
     commands.spawn((
-        SpriteBundle {
-            sprite: Sprite {
-                color: Color::MAROON,
-                custom_size: Some(Vec2::new(2000.0, 60.0)),
-                ..default()
-            },
-            transform: Transform::from_xyz(-200.0, -350.0, 0.0),
+        Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            visibility: Visibility::Hidden,
             ..default()
         },
-        Collision,
-        CollisionBox(Vec3::new(2000.0, 60.0, 0.0)),
+        InteractPrompt,
         GameElement,
     ));
 
-    // Teller
-    commands.spawn((
-        SpriteBundle {
-            texture: asset_server.load("sprites/teller.png"),
-            transform: Transform::from_xyz(-400.0, -300.0, 0.0)
-                .with_scale(Vec3::new(0.6, 0.6, 0.0)),
-            ..default()
-        },
-        Collision,
-        CollisionBox(Vec3::new(48.0, 70.0, 0.0)),
-        Teller,
-        TriggerBox(Vec3::new(60.0, 72.0, 0.0)),
-        GameElement,
-    ));
+    let level = shift
+        .level()
+        .expect("Level is loaded before starting a game")
+        .clone();
+    info!("Starting level {}", level.name);
+
+    commands.insert_resource(LevelTimer(Timer::new(level.time, TimerMode::Once)));
+    commands.insert_resource(DeliveryTimer(Timer::new(
+        level.delivery,
+        TimerMode::Repeating,
+    )));
+
+    level::spawn_level(&asset_server, &mut commands, &level);
+
+    let capacity = level.capacity + shift.campaign.bonus_slots();
 
     commands.spawn((
         SpriteBundle {
@@ -211,49 +415,54 @@ fn setup_game(
                 custom_size: Some(Vec2::new(64.0, 64.0)),
                 ..default()
             },
-            transform: Transform::from_xyz(-320.0, -280.0, 0.0),
+            transform: Transform::from_translation(level.player.extend(0.0)),
             ..default()
         },
         Player,
         Velocity(Vec3::ZERO),
         Acceleration(Vec3::ZERO),
+        Interpolated::new(level.player.extend(0.0)),
+        ControllerState::default(),
         Collision,
         CollisionBox(Vec3::new(64.0, 64.0, 0.0)),
-        Inventory::new(),
+        Inventory::new(capacity),
         GameElement,
     ));
 
-    let cake: CakeType = rand::random();
+    let mut menu = Menu::new(&level, recipes, &mut rng.0);
+    let special = menu
+        .special
+        .as_ref()
+        .and_then(|cake| recipes.get(cake))
+        .map(|recipe| recipe.name.clone());
+
+    // First customer is already waiting at the teller
+    let mut queue = CustomerQueue::default();
+    let cake = menu
+        .next_order(&mut rng.0)
+        .expect("Levels with nothing to order can't be selected");
+    queue.0.push(spawn_customer(
+        &asset_server,
+        recipes,
+        &mut commands,
+        level.customer_slot(0),
+        cake,
+    ));
+    commands.insert_resource(queue);
+    commands.insert_resource(menu);
+    commands.insert_resource(CustomerTimer(Timer::new(
+        CUSTOMER_INTERVAL,
+        TimerMode::Repeating,
+    )));
 
-    let id = commands
-        .spawn((
-            SpriteBundle {
-                sprite: Sprite {
-                    color: Color::CYAN,
-                    custom_size: Some(Vec2::new(64.0, 64.0)),
-                    ..default()
-                },
-                transform: Transform::from_xyz(-500.0, -200.0, 0.0),
-                ..default()
-            },
-            NPC {
-                wants: cake.clone(),
-            },
-            Velocity(Vec3::ZERO),
-            Acceleration(Vec3::ZERO),
-            Collision,
-            CollisionBox(Vec3::new(64.0, 64.0, 0.0)),
-            GameElement,
-        ))
-        .id();
+    // Game UI
 
     commands
         .spawn((
             NodeBundle {
                 style: Style {
-                    position_type: PositionType::Absolute,
-                    bottom: Val::Px(200.0),
-                    left: Val::Px(10.0),
+                    width: Val::Percent(50.0),
+                    height: Val::Percent(10.0),
                     ..default()
                 },
                 ..default()
@@ -268,14 +477,15 @@ fn setup_game(
                         align_items: AlignItems::Center,
                         ..default()
                     },
+                    background_color: Color::CRIMSON.into(),
                     ..default()
                 })
                 .with_children(|parent| {
                     parent.spawn((
                         TextBundle::from_section(
-                            format!("I want this cake, please!"),
+                            format!("Sold cakes: 0/{}", level.goal()),
                             TextStyle {
-                                font_size: 20.0,
+                                font_size: 30.0,
                                 color: Color::WHITE,
                                 ..default()
                             },
@@ -286,6 +496,7 @@ fn setup_game(
                             align_items: AlignItems::Center,
                             ..default()
                         }),
+                        ScoreUI,
                         // Because this is a distinct label widget and
                         // not button/list item text, this is necessary
                         // for accessibility to treat the text accordingly.
@@ -294,66 +505,6 @@ fn setup_game(
                 });
         });
 
-    spawn_display_cake(
-        &asset_server,
-        &mut commands,
-        Vec3::new(0.0, 60.0, 0.0),
-        cake,
-        &id,
-    );
-
-    spawn_ingredient(&asset_server, &mut commands, IngredientType::Eggs);
-    spawn_ingredient(&asset_server, &mut commands, IngredientType::Flour);
-    spawn_ingredient(&asset_server, &mut commands, IngredientType::Chocolate);
-    spawn_ingredient(&asset_server, &mut commands, IngredientType::Milk);
-    spawn_ingredient(&asset_server, &mut commands, IngredientType::Strawberry);
-    spawn_ingredient(&asset_server, &mut commands, IngredientType::Carrot);
-
-    // Cooking table
-    commands.spawn((
-        SpriteBundle {
-            texture: asset_server.load("sprites/cake_machine.png"),
-            transform: Transform::from_xyz(500.0, -220.0, 0.0).with_scale(Vec3::new(0.6, 0.6, 0.0)),
-            ..default()
-        },
-        Collision,
-        CollisionBox(Vec3::new(368.0 * 0.6, 369.0 * 0.6, 0.0)),
-        CookingTable,
-        TriggerBox(Vec3::new(368.0 * 0.65, 369.0 * 0.65, 0.0)),
-        GameElement,
-    ));
-
-    // Bin
-    commands.spawn((
-        SpriteBundle {
-            texture: asset_server.load("sprites/bin.png"),
-            transform: Transform::from_xyz(-300.0, -290.0, -10.0)
-                .with_scale(Vec3::new(0.6, 0.6, 0.0)),
-            ..default()
-        },
-        Bin,
-        TriggerBox(Vec3::new(50.0, 40.0, 0.0)),
-        GameElement,
-    ));
-
-    // Platform
-    commands.spawn((
-        SpriteBundle {
-            sprite: Sprite {
-                color: Color::MAROON,
-                custom_size: Some(Vec2::new(500.0, 20.0)),
-                ..default()
-            },
-            transform: Transform::from_translation(Vec3::new(0.0, -230.0, 0.0)),
-            ..default()
-        },
-        Collision,
-        CollisionBox(Vec3::new(500.0, 20.0, 0.0)),
-        GameElement,
-    ));
-
-    // Game UI
-
     commands
         .spawn((
             NodeBundle {
@@ -380,7 +531,7 @@ fn setup_game(
                 .with_children(|parent| {
                     parent.spawn((
                         TextBundle::from_section(
-                            format!("Sold cakes: 0"),
+                            format!("Time left 00:00"),
                             TextStyle {
                                 font_size: 30.0,
                                 color: Color::WHITE,
@@ -393,7 +544,7 @@ fn setup_game(
                             align_items: AlignItems::Center,
                             ..default()
                         }),
-                        ScoreUI,
+                        TimerUI,
                         // Because this is a distinct label widget and
                         // not button/list item text, this is necessary
                         // for accessibility to treat the text accordingly.
@@ -402,12 +553,52 @@ fn setup_game(
                 });
         });
 
+    if let Some(special) = special {
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        top: Val::Px(10.0),
+                        right: Val::Px(10.0),
+                        ..default()
+                    },
+                    background_color: Color::CRIMSON.into(),
+                    ..default()
+                },
+                EndScreen,
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    TextBundle::from_section(
+                        format!("Today's special: {special}"),
+                        TextStyle {
+                            font_size: 20.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    )
+                    .with_style(Style {
+                        margin: UiRect::all(Val::Px(10.0)),
+                        ..default()
+                    }),
+                    // Because this is a distinct label widget and
+                    // not button/list item text, this is necessary
+                    // for accessibility to treat the text accordingly.
+                    Label,
+                ));
+            });
+    }
+
+    // Inventory slots, along the bottom
     commands
         .spawn((
             NodeBundle {
                 style: Style {
-                    width: Val::Percent(50.0),
-                    height: Val::Percent(10.0),
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(10.0),
+                    width: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
                     ..default()
                 },
                 ..default()
@@ -415,50 +606,58 @@ fn setup_game(
             EndScreen,
         ))
         .with_children(|parent| {
-            parent
-                .spawn(NodeBundle {
-                    style: Style {
-                        flex_direction: FlexDirection::Column,
-                        align_items: AlignItems::Center,
-                        ..default()
-                    },
-                    background_color: Color::CRIMSON.into(),
-                    ..default()
-                })
-                .with_children(|parent| {
-                    parent.spawn((
-                        TextBundle::from_section(
-                            format!("Time left 00:00"),
-                            TextStyle {
-                                font_size: 30.0,
-                                color: Color::WHITE,
+            for slot in 0..capacity {
+                parent
+                    .spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Px(SLOT_SIZE),
+                                height: Val::Px(SLOT_SIZE),
+                                margin: UiRect::all(Val::Px(5.0)),
+                                border: UiRect::all(Val::Px(3.0)),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
                                 ..default()
                             },
-                        )
-                        .with_style(Style {
-                            margin: UiRect::all(Val::Px(20.0)),
-                            justify_content: JustifyContent::Center,
-                            align_items: AlignItems::Center,
+                            background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                            border_color: Color::GRAY.into(),
                             ..default()
-                        }),
-                        TimerUI,
-                        // Because this is a distinct label widget and
-                        // not button/list item text, this is necessary
-                        // for accessibility to treat the text accordingly.
-                        Label,
-                    ));
-                });
+                        },
+                        SlotUI(slot),
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            ImageBundle {
+                                style: Style {
+                                    width: Val::Px(SLOT_SIZE - 16.0),
+                                    height: Val::Px(SLOT_SIZE - 16.0),
+                                    ..default()
+                                },
+                                visibility: Visibility::Hidden,
+                                ..default()
+                            },
+                            SlotIcon(slot),
+                        ));
+                    });
+            }
         });
+
+    commands.insert_resource(CurrentLevel(level));
 }
 
 #[derive(Component)]
 struct ScoreUI;
 
-fn update_score_ui(score: Res<Score>, mut query: Query<&mut Text, With<ScoreUI>>) {
+fn update_score_ui(
+    score: Res<Score>,
+    level: Res<CurrentLevel>,
+    mut query: Query<&mut Text, With<ScoreUI>>,
+) {
     if score.is_changed() {
         let score = score.0;
+        let goal = level.0.goal();
         let mut score_ui = query.get_single_mut().expect("We got UI");
-        score_ui.sections[0].value = format!("Sold cakes: {score}");
+        score_ui.sections[0].value = format!("Sold cakes: {score}/{goal}");
     }
 }
 
@@ -477,6 +676,49 @@ fn update_timer_ui(score: Res<LevelTimer>, mut query: Query<&mut Text, With<Time
     }
 }
 
+/// Seconds between two physics steps
+const PHYSICS_STEP: f32 = 1.0 / 60.0;
+
+/// Physics runs at a fixed rate, the rendered transform is blended between the last two steps
+#[derive(Component)]
+struct Interpolated {
+    previous: Vec3,
+    current: Vec3,
+}
+
+impl Interpolated {
+    fn new(position: Vec3) -> Self {
+        Self {
+            previous: position,
+            current: position,
+        }
+    }
+}
+
+/// Puts back the simulated position before stepping physics
+fn restore_physics_transform_system(mut query: Query<(&mut Transform, &mut Interpolated)>) {
+    for (mut transform, mut interpolated) in query.iter_mut() {
+        transform.translation = interpolated.current;
+        interpolated.previous = interpolated.current;
+    }
+}
+
+fn record_physics_transform_system(mut query: Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in query.iter_mut() {
+        interpolated.current = transform.translation;
+    }
+}
+
+fn interpolate_transform_system(
+    time_step: Res<FixedTime>,
+    mut query: Query<(&mut Transform, &Interpolated)>,
+) {
+    let alpha = time_step.accumulated().as_secs_f32() / time_step.period.as_secs_f32();
+    for (mut transform, interpolated) in query.iter_mut() {
+        transform.translation = interpolated.previous.lerp(interpolated.current, alpha);
+    }
+}
+
 fn gravity_system(mut q_physics: Query<&mut Acceleration>) {
     for mut acc in q_physics.iter_mut() {
         //info!("oh gravity");
@@ -501,116 +743,281 @@ fn physics_system(
     }
 }
 
-#[derive(Event)]
-struct OnGroundEvent {
-    entity: Entity,
-}
+// Broadphase grid, about two players wide
+const COLLISION_CELL_SIZE: f32 = 128.0;
+
+/// Static geometry actors collide against
+type ColliderQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Transform,
+        &'static CollisionBox,
+        Option<&'static OneWayPlatform>,
+    ),
+    (
+        With<Collision>,
+        Without<Player>,
+        Without<NPC>,
+        Without<Loose>,
+    ),
+>;
+
+/// Everything that moves and gets pushed out of solids
+type ActorQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut Transform,
+        &'static CollisionBox,
+        &'static Interpolated,
+        &'static mut Acceleration,
+        &'static mut Velocity,
+        Option<&'static Leaving>,
+        Option<&'static Loose>,
+        Option<&'static DropThrough>,
+        Option<&'static mut ControllerState>,
+    ),
+    (With<Collision>, Or<(With<Player>, With<NPC>, With<Loose>)>),
+>;
 
 // NOTE: Assumes everything are rectangles
-fn collision_system(
-    q_colliders: Query<
-        (&Transform, &CollisionBox),
-        (With<Collision>, Without<Player>, Without<NPC>),
-    >,
-    mut q_player: Query<
-        (
-            Entity,
-            &mut Transform,
-            &Sprite,
-            &mut Acceleration,
-            &mut Velocity,
-        ),
-        (With<Collision>, Or<(With<Player>, With<NPC>)>),
-    >,
-    mut my_events: EventWriter<OnGroundEvent>,
-) {
-    // TODO: could be simplified with iter_combinations?
-    for (ent, mut player_trans, player_sprite, mut acc, mut vel) in q_player.iter_mut() {
-        for (transform, sprite) in q_colliders.iter() {
-            //info!("checking collisions");
-            let collision = collide(
-                player_trans.translation,
-                player_sprite.custom_size.unwrap(),
-                transform.translation,
-                sprite.0.truncate(),
-            );
+fn collision_system(q_colliders: ColliderQuery, mut q_actors: ActorQuery) {
+    let solids: Vec<(Aabb, bool)> = q_colliders
+        .iter()
+        .map(|(transform, size, one_way)| {
+            (
+                Aabb::new(transform.translation.truncate(), size.0.truncate()),
+                one_way.is_some(),
+            )
+        })
+        .collect();
+    let mut broadphase = SpatialHash::new(COLLISION_CELL_SIZE);
+    for (index, (solid, _)) in solids.iter().enumerate() {
+        broadphase.insert(index, solid);
+    }
 
-            if let Some(collision) = collision {
-                //info!("collision");
-                let half_size = sprite.0.truncate() / 2.0;
-                let player_half_size = player_sprite.custom_size.unwrap() / 2.0;
-
-                match collision {
-                    bevy::sprite::collide_aabb::Collision::Left => {
-                        acc.0.x = 0.0;
-                        vel.0.x = 0.0;
-                        player_trans.translation.x =
-                            transform.translation.x - half_size.x - player_half_size.x
-                    }
-                    bevy::sprite::collide_aabb::Collision::Right => {
-                        acc.0.x = 0.0;
-                        vel.0.x = 0.0;
-                        player_trans.translation.x =
-                            transform.translation.x + half_size.x + player_half_size.x
-                    }
-                    bevy::sprite::collide_aabb::Collision::Top => {
-                        my_events.send(OnGroundEvent { entity: ent });
-                        acc.0.y = 0.0;
-                        vel.0.y = 0.0;
-                        player_trans.translation.y =
-                            transform.translation.y + half_size.y + player_half_size.y
-                    }
-                    bevy::sprite::collide_aabb::Collision::Bottom => {
-                        acc.0.y = 0.0;
-                        vel.0.y = 0.0;
-                        player_trans.translation.y =
-                            transform.translation.y - half_size.y - player_half_size.y
-                    }
-                    bevy::sprite::collide_aabb::Collision::Inside => unreachable!(),
-                }
-            }
+    // Replay this step's moves from where they started, so fast falls can't skip a platform
+    let mut actors: Vec<(Entity, Aabb, Vec2, bool, bool)> = q_actors
+        .iter()
+        .map(
+            |(ent, transform, size, interpolated, .., leaving, loose, dropping, _)| {
+                let start = interpolated.previous.truncate();
+                (
+                    ent,
+                    Aabb::new(start, size.0.truncate()),
+                    transform.translation.truncate() - start,
+                    leaving.is_some() || loose.is_some(),
+                    dropping.is_some(),
+                )
+            },
+        )
+        .collect();
+    // Actors move during the loop, so they are bucketed by all the way they might go
+    let mut actor_broadphase = SpatialHash::new(COLLISION_CELL_SIZE);
+    for (index, &(_, aabb, motion, ..)) in actors.iter().enumerate() {
+        actor_broadphase.insert(index, &aabb.swept(motion));
+    }
+
+    for index in 0..actors.len() {
+        let (ent, aabb, motion, ghost, dropping) = actors[index];
+
+        let mut obstacles = Vec::new();
+        let mut platforms = Vec::new();
+        for solid in broadphase.query(&aabb.swept(motion)) {
+            match solids[solid] {
+                (platform, true) if !dropping => platforms.push(platform),
+                (_, true) => {}
+                (solid, false) => obstacles.push(solid),
+            }
+        }
+        // Actors bump into each other, except customers walking out and items on the
+        // floor which just go through
+        if !ghost {
+            obstacles.extend(
+                actor_broadphase
+                    .query(&aabb.swept(motion))
+                    .into_iter()
+                    .filter(|&other| other != index && !actors[other].3)
+                    .map(|other| actors[other].1),
+            );
+        }
+
+        let contact = collision::move_and_collide(aabb, motion, &obstacles, &platforms);
+        actors[index].1.center = contact.position;
+
+        let (_, mut trans, _, _, mut acc, mut vel, .., state) =
+            q_actors.get_mut(ent).expect("Actor was just queried");
+        trans.translation.x = contact.position.x;
+        trans.translation.y = contact.position.y;
+
+        if contact.hit_wall {
+            acc.0.x = 0.0;
+            vel.0.x = 0.0;
+        }
+        if contact.on_ground || contact.hit_ceiling {
+            acc.0.y = 0.0;
+            vel.0.y = 0.0;
+        }
+        // Kept on the actor rather than sent as an event, events don't survive
+        // the frames that can pass between two fixed steps
+        if let Some(mut state) = state {
+            state.grounded = contact.on_ground;
+        }
+    }
+}
+
+/// Tuning of the player's movement, speeds in pixels per second
+#[derive(Resource)]
+struct PlatformerController {
+    max_speed: f32,
+    ground_accel: f32,
+    ground_decel: f32,
+    /// Share of the ground acceleration available while airborne
+    air_control: f32,
+    jump_speed: f32,
+    /// What is left of the upward speed when the jump is let go early
+    jump_cut: f32,
+    /// Seconds after leaving the ground a jump is still allowed
+    coyote_time: f32,
+    /// Seconds a jump pressed too early is remembered
+    jump_buffer: f32,
+}
+
+impl Default for PlatformerController {
+    fn default() -> Self {
+        Self {
+            max_speed: 220.0,
+            ground_accel: 1800.0,
+            ground_decel: 2400.0,
+            air_control: 0.6,
+            jump_speed: 500.0,
+            jump_cut: 0.5,
+            coyote_time: 0.1,
+            jump_buffer: 0.12,
         }
     }
 }
 
 #[derive(Component)]
-struct Jumping;
+struct ControllerState {
+    /// Standing on something at the end of the last physics step
+    grounded: bool,
+    /// Going up from a jump that can still be cut short
+    rising: bool,
+    since_grounded: f32,
+    since_jump_pressed: f32,
+}
 
-fn jump_system(
+impl Default for ControllerState {
+    fn default() -> Self {
+        Self {
+            grounded: false,
+            rising: false,
+            since_grounded: f32::INFINITY,
+            since_jump_pressed: f32::INFINITY,
+        }
+    }
+}
+
+/// How long one-way platforms are ignored after asking to drop through them
+const DROP_THROUGH_TIME: Duration = Duration::from_millis(250);
+
+#[derive(Component)]
+struct DropThrough(Timer);
+
+fn drop_through_system(
     mut commands: Commands,
-    keyboard_input: Res<Input<KeyCode>>,
-    mut player: Query<(Entity, &mut Acceleration, Option<&Jumping>), With<Player>>,
-    mut events: EventReader<OnGroundEvent>,
+    actions: Res<ActionState>,
+    time_step: Res<FixedTime>,
+    mut player: Query<(Entity, Option<&mut DropThrough>), With<Player>>,
 ) {
-    for OnGroundEvent { entity } in events.iter() {
-        commands.entity(*entity).remove::<Jumping>();
+    for (ent, dropping) in player.iter_mut() {
+        if let Some(mut dropping) = dropping {
+            dropping.0.tick(time_step.period);
+            if dropping.0.finished() {
+                commands.entity(ent).remove::<DropThrough>();
+            }
+        } else if actions.fixed_just_pressed(Action::Drop) {
+            commands
+                .entity(ent)
+                .insert(DropThrough(Timer::new(DROP_THROUGH_TIME, TimerMode::Once)));
+        }
     }
-    for (ent, mut acc, is_jumping) in player.iter_mut() {
-        if is_jumping.is_none() && keyboard_input.just_pressed(KeyCode::Up) {
-            acc.0.y += 500.0;
-            commands.entity(ent).insert(Jumping);
+}
+
+fn jump_system(
+    actions: Res<ActionState>,
+    controller: Res<PlatformerController>,
+    time_step: Res<FixedTime>,
+    mut player: Query<(&mut Velocity, &mut ControllerState), With<Player>>,
+) {
+    let dt = time_step.period.as_secs_f32();
+
+    for (mut vel, mut state) in player.iter_mut() {
+        if state.grounded {
+            state.since_grounded = 0.0;
+            state.rising = false;
+        } else {
+            state.since_grounded += dt;
+        }
+
+        if actions.fixed_just_pressed(Action::Jump) {
+            state.since_jump_pressed = 0.0;
+        } else {
+            state.since_jump_pressed += dt;
+        }
+
+        // Coyote time lets a jump through just after running off a ledge,
+        // the buffer remembers a press made just before landing
+        let can_jump = state.since_grounded <= controller.coyote_time && !state.rising;
+        if can_jump && state.since_jump_pressed <= controller.jump_buffer {
+            vel.0.y = controller.jump_speed;
+            state.rising = true;
+            state.since_grounded = f32::INFINITY;
+            state.since_jump_pressed = f32::INFINITY;
+        }
+
+        // Letting go early makes for a shorter jump
+        if state.rising && vel.0.y > 0.0 && !actions.pressed(Action::Jump) {
+            vel.0.y *= controller.jump_cut;
+            state.rising = false;
         }
     }
 }
 
 fn movement_system(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut player: Query<&mut Transform, With<Player>>,
+    actions: Res<ActionState>,
+    controller: Res<PlatformerController>,
+    mut player: Query<(&mut Velocity, &ControllerState), With<Player>>,
     time_step: Res<FixedTime>,
 ) {
-    let mut player = player.get_single_mut().expect("Always a player");
+    let (mut vel, state) = player.get_single_mut().expect("Always a player");
+
+    let direction = if actions.pressed(Action::MoveLeft) {
+        -1.0
+    } else if actions.pressed(Action::MoveRight) {
+        1.0
+    } else {
+        0.0
+    };
 
-    if keyboard_input.pressed(KeyCode::Left) {
-        player.translation.x -= 100.0 * time_step.period.as_secs_f32();
-    } else if keyboard_input.pressed(KeyCode::Right) {
-        player.translation.x += 100.0 * time_step.period.as_secs_f32();
+    let mut accel = if direction == 0.0 {
+        controller.ground_decel
+    } else {
+        controller.ground_accel
+    };
+    if !state.grounded {
+        accel *= controller.air_control;
     }
-}
 
-#[derive(Component)]
-struct Ingredient(IngredientType);
+    let target = direction * controller.max_speed;
+    let step = accel * time_step.period.as_secs_f32();
+    vel.0.x += (target - vel.0.x).clamp(-step, step);
+}
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 enum IngredientType {
     Eggs,
     Flour,
@@ -620,240 +1027,1195 @@ enum IngredientType {
     Carrot,
 }
 
-fn spawn_ingredient(
+impl FromStr for IngredientType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Eggs" => Ok(IngredientType::Eggs),
+            "Flour" => Ok(IngredientType::Flour),
+            "Chocolate" => Ok(IngredientType::Chocolate),
+            "Milk" => Ok(IngredientType::Milk),
+            "Strawberry" => Ok(IngredientType::Strawberry),
+            "Carrot" => Ok(IngredientType::Carrot),
+            _ => Err(()),
+        }
+    }
+}
+
+fn ingredient_texture(
     asset_server: &Res<AssetServer>,
-    commands: &mut Commands,
+    ingredient: &IngredientType,
+) -> Handle<Image> {
+    match ingredient {
+        IngredientType::Eggs => asset_server.load("sprites/eggs.png"),
+        IngredientType::Flour => asset_server.load("sprites/flour.png"),
+        IngredientType::Chocolate => asset_server.load("sprites/chocolate.png"),
+        IngredientType::Milk => asset_server.load("sprites/milk.png"),
+        IngredientType::Strawberry => asset_server.load("sprites/strawberry.png"),
+        IngredientType::Carrot => asset_server.load("sprites/carrot.png"),
+    }
+}
+
+// DISPENSERS
+
+/// Ingredients left in a dispenser
+#[derive(Component)]
+struct Stock {
     ingredient: IngredientType,
+    count: u32,
+}
+
+#[derive(Resource)]
+struct DeliveryTimer(Timer);
+
+/// Ingredients each dispenser gets per delivery
+const DELIVERY_SIZE: u32 = 3;
+
+/// Hands out one ingredient per use, as long as there are some left
+fn dispenser_system(
+    mut commands: Commands,
+    mut events: StayEvents,
+    interact: InteractCheck,
+    mut q_stock: Query<&mut Stock>,
+    mut q_player: Query<&mut Inventory, With<Player>>,
+    sprites: ItemSprites,
 ) {
-    let color = {
-        match ingredient {
-            IngredientType::Eggs => asset_server.load("sprites/eggs.png"),
-            IngredientType::Flour => asset_server.load("sprites/flour.png"),
-            IngredientType::Chocolate => asset_server.load("sprites/chocolate.png"),
-            IngredientType::Milk => asset_server.load("sprites/milk.png"),
-            IngredientType::Strawberry => asset_server.load("sprites/strawberry.png"),
-            IngredientType::Carrot => asset_server.load("sprites/carrot.png"),
+    for (TriggerStay { trigger, actor }, entered) in events.read() {
+        let (Ok(mut stock), Ok(mut inventory)) =
+            (q_stock.get_mut(trigger), q_player.get_mut(actor))
+        else {
+            continue;
+        };
+
+        if stock.count == 0 || !interact.fires_once(trigger, entered) {
+            continue;
         }
-    };
 
-    let position = {
-        match ingredient {
-            IngredientType::Eggs => Vec3::new(-30.0, -300.0, 0.0),
-            IngredientType::Flour => Vec3::new(30.0, -300.0, 0.0),
-            IngredientType::Milk => Vec3::new(0.0, -0.0, 0.0),
-            IngredientType::Chocolate => Vec3::new(20.0, -190.0, 0.0),
-            IngredientType::Strawberry => Vec3::new(-150.0, -190.0, 0.0),
-            IngredientType::Carrot => Vec3::new(200.0, -190.0, 0.0),
+        let item = Item::Ingredient(stock.ingredient.clone());
+        let Some(slot) = inventory.add(item.clone()) else {
+            // Inventory full
+            continue;
+        };
+        stock.count -= 1;
+
+        let transform = carried_transform(&inventory, slot);
+        spawn_product(
+            &sprites.asset_server,
+            &sprites.recipes,
+            &mut commands,
+            &item,
+            transform,
+            &actor,
+        );
+    }
+}
+
+fn delivery_system(
+    time: Res<Time>,
+    mut timer: ResMut<DeliveryTimer>,
+    mut q_stock: Query<&mut Stock>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    for mut stock in q_stock.iter_mut() {
+        stock.count += DELIVERY_SIZE;
+    }
+}
+
+fn stock_text_system(
+    mut q_stock: Query<(&Stock, &Children, &mut Sprite, Option<&mut Highlighted>), Changed<Stock>>,
+    mut q_text: Query<&mut Text>,
+) {
+    for (stock, children, mut sprite, highlighted) in q_stock.iter_mut() {
+        // Grayed out until the next delivery
+        let color = if stock.count == 0 {
+            Color::rgba(0.5, 0.5, 0.5, 0.5)
+        } else {
+            Color::WHITE
+        };
+        match highlighted {
+            Some(mut highlighted) => highlighted.0 = color,
+            None => sprite.color = color,
         }
-    };
 
-    commands.spawn((
-        SpriteBundle {
-            texture: color,
-            transform: Transform::from_translation(position).with_scale(Vec3::new(0.8, 0.8, 0.0)),
-            ..default()
-        },
-        Ingredient(ingredient),
-        TriggerBox(Vec3::new(40.0, 40.0, 0.0)),
-        GameElement,
-    ));
+        let mut texts = q_text.iter_many_mut(children);
+        while let Some(mut text) = texts.fetch_next() {
+            text.sections[0].value = format!("x{}", stock.count);
+            text.sections[0].style.color = if stock.count == 0 {
+                Color::RED
+            } else {
+                Color::WHITE
+            };
+        }
+    }
 }
 
 #[derive(Component)]
 struct Teller;
 
-fn trigger_ingredient_system(
+// TRIGGERS
+
+/// An actor started overlapping a [`TriggerBox`]
+#[derive(Event)]
+struct TriggerEnter {
+    trigger: Entity,
+    actor: Entity,
+}
+
+/// An actor overlaps a [`TriggerBox`], sent every frame including the one it entered
+#[derive(Event)]
+struct TriggerStay {
+    trigger: Entity,
+    actor: Entity,
+}
+
+/// Stay events, each with whether the actor only just entered
+#[derive(SystemParam)]
+struct StayEvents<'w, 's> {
+    enter: EventReader<'w, 's, TriggerEnter>,
+    stay: EventReader<'w, 's, TriggerStay>,
+}
+
+impl StayEvents<'_, '_> {
+    fn read(&mut self) -> Vec<(TriggerStay, bool)> {
+        let entered: HashSet<(Entity, Entity)> = self
+            .enter
+            .iter()
+            .map(|&TriggerEnter { trigger, actor }| (trigger, actor))
+            .collect();
+        self.stay
+            .iter()
+            .map(|&TriggerStay { trigger, actor }| {
+                (
+                    TriggerStay { trigger, actor },
+                    entered.contains(&(trigger, actor)),
+                )
+            })
+            .collect()
+    }
+}
+
+/// An actor stopped overlapping a [`TriggerBox`], which might have been despawned since
+#[derive(Event)]
+struct TriggerExit {
+    trigger: Entity,
+    actor: Entity,
+}
+
+/// Trigger and actor pairs overlapping on the last frame
+#[derive(Resource, Default)]
+struct TriggerContacts(HashSet<(Entity, Entity)>);
+
+fn trigger_system(
+    q_triggers: Query<(Entity, &Transform, &TriggerBox)>,
+    q_actors: Query<(Entity, &Transform, &CollisionBox), With<Player>>,
+    mut contacts: ResMut<TriggerContacts>,
+    mut enter_events: EventWriter<TriggerEnter>,
+    mut stay_events: EventWriter<TriggerStay>,
+    mut exit_events: EventWriter<TriggerExit>,
+) {
+    let mut overlapping = HashSet::new();
+    for (actor, actor_trans, actor_box) in q_actors.iter() {
+        let actor_box = Aabb::new(actor_trans.translation.truncate(), actor_box.0.truncate());
+        for (trigger, transform, trigger_box) in q_triggers.iter() {
+            let trigger_box = Aabb::new(transform.translation.truncate(), trigger_box.0.truncate());
+            if actor_box.overlaps(&trigger_box) {
+                overlapping.insert((trigger, actor));
+            }
+        }
+    }
+
+    for &(trigger, actor) in overlapping.iter() {
+        if !contacts.0.contains(&(trigger, actor)) {
+            enter_events.send(TriggerEnter { trigger, actor });
+        }
+        stay_events.send(TriggerStay { trigger, actor });
+    }
+    for &(trigger, actor) in contacts.0.difference(&overlapping) {
+        exit_events.send(TriggerExit { trigger, actor });
+    }
+
+    contacts.0 = overlapping;
+}
+
+const HIGHLIGHT: Color = Color::rgb(1.0, 1.0, 0.6);
+
+/// Color of a sprite before it was highlighted
+#[derive(Component)]
+struct Highlighted(Color);
+
+/// Tints whatever the player is standing at
+fn trigger_highlight_system(
     mut commands: Commands,
-    mut q: Query<(Entity, &mut Transform, &TriggerBox, &Ingredient), Without<Player>>,
-    mut q_player: Query<(Entity, &Transform, &Sprite, &mut Inventory), With<Player>>,
+    mut enter_events: EventReader<TriggerEnter>,
+    mut exit_events: EventReader<TriggerExit>,
+    q_player: Query<(), With<Player>>,
+    mut q_sprite: Query<(&mut Sprite, Option<&Highlighted>)>,
 ) {
-    let (player, player_trans, player_sprite, mut inventory) =
-        q_player.get_single_mut().expect("Always a player");
-
-    for (ingredient, mut transform, sprite, Ingredient(ing)) in q.iter_mut() {
-        //info!("checking collisions");
-        let collision = collide(
-            player_trans.translation,
-            player_sprite.custom_size.unwrap(),
-            transform.translation,
-            sprite.0.truncate(),
-        );
+    for &TriggerExit { trigger, actor } in exit_events.iter() {
+        let Ok((mut sprite, Some(Highlighted(color)))) = q_sprite.get_mut(trigger) else {
+            continue;
+        };
+        if q_player.contains(actor) {
+            sprite.color = *color;
+            commands.entity(trigger).remove::<Highlighted>();
+        }
+    }
+    for &TriggerEnter { trigger, actor } in enter_events.iter() {
+        let Ok((mut sprite, None)) = q_sprite.get_mut(trigger) else {
+            continue;
+        };
+        if q_player.contains(actor) {
+            commands.entity(trigger).insert(Highlighted(sprite.color));
+            sprite.color = HIGHLIGHT;
+        }
+    }
+}
 
-        if collision.is_some() && !inventory.items.contains(&Some(ing.clone())) {
-            let toto = inventory.items.iter_mut().find(|el| el.is_none());
+// INTERACT PROMPT
 
-            if let Some(toto) = toto {
-                *toto = Some(ing.clone());
-            } else {
-                // Inventory full
-                break;
+/// Trigger the player would use by pressing interact, and what would happen
+#[derive(Resource, Default)]
+struct InteractFocus {
+    trigger: Option<Entity>,
+    prompt: String,
+}
+
+/// Whether a station should act on a player standing at it
+#[derive(SystemParam)]
+struct InteractCheck<'w> {
+    settings: Res<'w, Settings>,
+    actions: Res<'w, ActionState>,
+    focus: Res<'w, InteractFocus>,
+}
+
+impl InteractCheck<'_> {
+    fn fires(&self, trigger: Entity) -> bool {
+        !self.settings.interact_mode
+            || (self.focus.trigger == Some(trigger) && self.actions.just_pressed(Action::Interact))
+    }
+
+    /// For things that can be used over and over: without the interact button,
+    /// only fires when the player walks up to it
+    fn fires_once(&self, trigger: Entity, entered: bool) -> bool {
+        if self.settings.interact_mode {
+            self.fires(trigger)
+        } else {
+            entered
+        }
+    }
+}
+
+/// Triggers the player might interact with, and what they hold
+type FocusTriggerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Transform,
+        Option<&'static Stock>,
+        Option<&'static Product>,
+        Option<&'static Teller>,
+        Option<(&'static CookingTable, &'static Batch)>,
+        Option<&'static Bin>,
+    ),
+    Without<JustDropped>,
+>;
+
+/// Picks the closest trigger the player can use, in interact mode
+fn interact_focus_system(
+    contacts: Res<TriggerContacts>,
+    settings: Res<Settings>,
+    recipes: Res<Recipes>,
+    q_player: Query<(&Transform, &Inventory), With<Player>>,
+    q_triggers: FocusTriggerQuery,
+    mut focus: ResMut<InteractFocus>,
+) {
+    focus.trigger = None;
+    if !settings.interact_mode {
+        return;
+    }
+
+    let interact = settings.bindings.primary(Action::Interact);
+    let mut closest = f32::INFINITY;
+
+    for &(trigger, actor) in contacts.0.iter() {
+        let (Ok((player_trans, inventory)), Ok((transform, stock, product, teller, table, bin))) =
+            (q_player.get(actor), q_triggers.get(trigger))
+        else {
+            continue;
+        };
+
+        let prompt = if let Some(Stock { ingredient, count }) = stock {
+            (inventory.has_room() && *count > 0)
+                .then(|| format!("[{interact}] Take {ingredient:?} ({count} left)"))
+        } else if let Some(Product(item)) = product {
+            inventory
+                .has_room()
+                .then(|| format!("[{interact}] Take {}", item.describe(&recipes)))
+        } else if teller.is_some() {
+            inventory
+                .items
+                .iter()
+                .flatten()
+                .find(|item| matches!(item, Item::Cake(Baked::Cake(_))))
+                .map(|cake| format!("[{interact}] Serve {}", cake.describe(&recipes)))
+        } else if let Some((CookingTable(station), batch)) = table {
+            let verb = station.verb();
+            match batch {
+                Batch::Empty => inventory
+                    .next_for(*station, &recipes)
+                    .map(|item| item.describe(&recipes))
+                    .or_else(|| {
+                        recipe::find_recipe(&recipes.0, &inventory.carried(), *station)
+                            .map(|craft| craft.name().to_string())
+                    })
+                    .map(|name| format!("[{interact}] {verb} {name}")),
+                Batch::Working { .. } => None,
+                Batch::Ready { product, .. } => inventory
+                    .has_room()
+                    .then(|| format!("[{interact}] Take {}", product.describe(&recipes))),
             }
+        } else if bin.is_some() && inventory.items.iter().any(Option::is_some) {
+            Some(format!(
+                "[{}] Empty inventory",
+                settings.bindings.primary(Action::Drop)
+            ))
+        } else {
+            None
+        };
+
+        let distance = player_trans.translation.distance(transform.translation);
+        if let Some(prompt) = prompt.filter(|_| distance < closest) {
+            closest = distance;
+            focus.trigger = Some(trigger);
+            focus.prompt = prompt;
+        }
+    }
+}
 
-            let count = inventory.items.iter().filter(|&el| el.is_some()).count();
+#[derive(Component)]
+struct InteractPrompt;
 
-            let diff = match count {
-                0 => unreachable!(),
-                1 => -1.5,
-                2 => -0.75,
-                3 => 0.75,
-                4 => 1.5,
-                _ => unreachable!(),
-            };
+fn interact_prompt_system(
+    focus: Res<InteractFocus>,
+    q_triggers: Query<(&Transform, &TriggerBox), Without<InteractPrompt>>,
+    mut q_prompt: Query<(&mut Text, &mut Transform, &mut Visibility), With<InteractPrompt>>,
+) {
+    let Ok((mut text, mut transform, mut visibility)) = q_prompt.get_single_mut() else {
+        return;
+    };
 
-            commands.entity(ingredient).set_parent(player);
-            transform.scale = Vec3::new(0.3, 0.3, 0.0);
-            transform.translation = Vec3::new(10.0 * diff, 10.0, 10.0);
+    match focus
+        .trigger
+        .and_then(|trigger| q_triggers.get(trigger).ok())
+    {
+        Some((trigger_trans, trigger_box)) => {
+            // Float just above the station
+            transform.translation =
+                trigger_trans.translation + Vec3::new(0.0, trigger_box.0.y / 2.0 + 20.0, 50.0);
+            text.sections[0].value = focus.prompt.clone();
+            *visibility = Visibility::Visible;
         }
+        None => *visibility = Visibility::Hidden,
     }
 }
 
-fn teller_system(
+/// Items lying on the floor, ready to be picked up
+type LooseItemQuery<'w, 's> =
+    Query<'w, 's, (&'static mut Transform, &'static Product), (With<Loose>, Without<JustDropped>)>;
+
+/// Picks up items lying on the floor
+fn pickup_system(
     mut commands: Commands,
-    mut q: Query<(&mut Transform, &TriggerBox), (With<Teller>, Without<Player>)>,
-    mut q_npc: Query<
-        (Entity, &mut Transform, &mut NPC),
-        (Without<Player>, With<NPC>, Without<Teller>),
-    >,
-    mut q_player: Query<
-        (
-            Entity,
-            &Transform,
-            &CollisionBox,
-            &mut Inventory,
-            Option<&Children>,
-        ),
-        With<Player>,
-    >,
-    q_ingredients: Query<(Entity, &Cake)>,
-    mut score: ResMut<Score>,
-    asset_server: Res<AssetServer>,
+    mut events: EventReader<TriggerStay>,
+    interact: InteractCheck,
+    mut q: LooseItemQuery,
+    mut q_player: Query<&mut Inventory, With<Player>>,
 ) {
-    let (_player, player_trans, player_sprite, mut inventory, _children) =
-        q_player.get_single_mut().expect("Always a player");
-
-    for (transform, sprite) in q.iter_mut() {
-        //info!("checking collisions");
-        let collision = collide(
-            player_trans.translation,
-            player_sprite.0.truncate(),
-            transform.translation,
-            sprite.0.truncate(),
-        );
+    for &TriggerStay { trigger, actor } in events.iter() {
+        let (Ok((mut transform, Product(item))), Ok(mut inventory)) =
+            (q.get_mut(trigger), q_player.get_mut(actor))
+        else {
+            continue;
+        };
+
+        if interact.fires(trigger) {
+            let Some(slot) = inventory.add(item.clone()) else {
+                // Inventory full
+                continue;
+            };
 
-        if collision.is_some() {
-            if let Some(_cake) = &inventory.cake {
-                let (npc_e, mut npc_trans, mut npc) =
-                    q_npc.get_single_mut().expect("Always an NPC");
+            // Carried along now, not something to pick up or that falls on its own
+            commands.entity(trigger).set_parent(actor).remove::<(
+                TriggerBox,
+                Loose,
+                Collision,
+                CollisionBox,
+                Velocity,
+                Acceleration,
+                Interpolated,
+            )>();
+
+            *transform = carried_transform(&inventory, slot);
+        }
+    }
+}
 
-                let (ent, Cake(cake)) = q_ingredients.get_single().expect("Should be a cake there");
-                if npc.wants == *cake {
-                    score.0 += 1;
+// DROPPING ITEMS
 
-                    commands.entity(ent).despawn_recursive();
-                    commands.entity(npc_e).despawn_descendants();
+/// Put down by the player, falls and can be picked up again
+#[derive(Component)]
+struct Loose;
 
-                    inventory.cake = None;
-                    npc_trans.translation.y += 50.0;
-                    npc.wants = rand::random();
+/// Can't be picked up before the player steps away, or it would come straight back
+#[derive(Component)]
+struct JustDropped;
 
-                    spawn_display_cake(
-                        &asset_server,
-                        &mut commands,
-                        Vec3::new(0.0, 60.0, 0.0),
-                        npc.wants.clone(),
-                        &npc_e,
-                    );
-                }
-            }
+const LOOSE_SIZE: Vec3 = Vec3::new(30.0, 30.0, 0.0);
+
+fn select_slot_system(
+    actions: Res<ActionState>,
+    mut q_player: Query<&mut Inventory, With<Player>>,
+) {
+    if actions.just_pressed(Action::NextSlot) {
+        for mut inventory in q_player.iter_mut() {
+            inventory.selected = (inventory.selected + 1) % inventory.items.len();
         }
     }
 }
 
-#[derive(Component)]
-struct Inventory {
-    items: [Option<IngredientType>; 4],
-    cake: Option<CakeType>,
+fn drop_item_system(
+    mut commands: Commands,
+    actions: Res<ActionState>,
+    mut q_player: Query<(&Transform, &mut Inventory, &Children), With<Player>>,
+    q_carried: Query<(&Transform, &Product)>,
+) {
+    if !actions.just_pressed(Action::DropItem) {
+        return;
+    }
+
+    for (player_trans, mut inventory, children) in q_player.iter_mut() {
+        let selected = inventory.selected;
+        let Some(item) = inventory.items[selected].clone() else {
+            continue;
+        };
+        // Burnt cakes only go in the bin, not back on the floor
+        if matches!(item, Item::Cake(Baked::Burnt(_))) {
+            continue;
+        }
+
+        let carried = children.iter().find_map(|&child| {
+            let (transform, Product(carried)) = q_carried.get(child).ok()?;
+            (*carried == item).then_some((child, transform.scale))
+        });
+        let Some((child, scale)) = carried else {
+            continue;
+        };
+
+        inventory.items[selected] = None;
+
+        let position = player_trans.translation + Vec3::Z * 10.0;
+        let scale = match item {
+            // Back to the size it has at the dispenser
+            Item::Ingredient(_) => Vec3::new(0.8, 0.8, 0.0),
+            _ => scale,
+        };
+        commands.entity(child).remove_parent().insert((
+            Transform::from_translation(position).with_scale(scale),
+            Loose,
+            JustDropped,
+            Collision,
+            CollisionBox(LOOSE_SIZE),
+            TriggerBox(Vec3::new(40.0, 40.0, 0.0)),
+            Velocity(Vec3::new(0.0, 200.0, 0.0)),
+            Acceleration(Vec3::ZERO),
+            Interpolated::new(position),
+        ));
+    }
 }
 
-impl Inventory {
-    fn new() -> Self {
-        Self {
-            items: [None, None, None, None],
-            cake: None,
+fn just_dropped_system(
+    mut commands: Commands,
+    mut exit_events: EventReader<TriggerExit>,
+    q_dropped: Query<(), With<JustDropped>>,
+) {
+    for &TriggerExit { trigger, .. } in exit_events.iter() {
+        if q_dropped.contains(trigger) {
+            commands.entity(trigger).remove::<JustDropped>();
         }
     }
 }
 
+// INVENTORY
+
+/// Size of an inventory slot on screen
+const SLOT_SIZE: f32 = 64.0;
+
+/// Width over which ingredients in hand are spread
+const HANDS_WIDTH: f32 = 48.0;
+
 #[derive(Component)]
-struct CookingTable;
+struct SlotUI(usize);
 
 #[derive(Component)]
-struct Cake(CakeType);
+struct SlotIcon(usize);
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-enum CakeType {
-    Chocolate,
-    Fraisier,
-    Carrot,
+fn update_inventory_ui(
+    recipes: Res<Recipes>,
+    asset_server: Res<AssetServer>,
+    q_player: Query<&Inventory, (With<Player>, Changed<Inventory>)>,
+    mut q_slot: Query<(&SlotUI, &mut BorderColor)>,
+    mut q_icon: Query<(
+        &SlotIcon,
+        &mut UiImage,
+        &mut BackgroundColor,
+        &mut Visibility,
+    )>,
+) {
+    let Ok(inventory) = q_player.get_single() else {
+        return;
+    };
+
+    for (SlotUI(slot), mut border) in q_slot.iter_mut() {
+        border.0 = if *slot == inventory.selected {
+            Color::YELLOW
+        } else {
+            Color::GRAY
+        };
+    }
+
+    for (SlotIcon(slot), mut image, mut tint, mut visibility) in q_icon.iter_mut() {
+        match inventory.items.get(*slot).and_then(Option::as_ref) {
+            Some(item) => {
+                image.texture = item_texture(&asset_server, &recipes, item);
+                tint.0 = product_look(item).0;
+                *visibility = Visibility::Inherited;
+            }
+            None => *visibility = Visibility::Hidden,
+        }
+    }
+}
+
+/// Where a carried item sits on the player: ingredients are fanned out in hand,
+/// anything made at a station is stacked on the head
+fn carried_transform(inventory: &Inventory, slot: usize) -> Transform {
+    let in_hand = |item: &Item| matches!(item, Item::Ingredient(_));
+    let Some(item) = inventory.items[slot].as_ref() else {
+        return Transform::IDENTITY;
+    };
+
+    let group: Vec<usize> = inventory
+        .items
+        .iter()
+        .enumerate()
+        .filter(|(_, other)| {
+            other
+                .as_ref()
+                .is_some_and(|other| in_hand(other) == in_hand(item))
+        })
+        .map(|(index, _)| index)
+        .collect();
+    let index = group.iter().position(|&other| other == slot).unwrap_or(0) as f32;
+
+    if in_hand(item) {
+        let spacing = match group.len() {
+            1 => 0.0,
+            count => (HANDS_WIDTH / (count - 1) as f32).min(15.0),
+        };
+        let x = (index - (group.len() - 1) as f32 / 2.0) * spacing;
+        Transform::from_xyz(x, 10.0, 10.0).with_scale(Vec3::new(0.3, 0.3, 0.0))
+    } else {
+        let (_, scale) = product_look(item);
+        Transform::from_xyz(0.0, 40.0 + 30.0 * index, 10.0 + index)
+            .with_scale(Vec3::new(scale, scale, 0.0))
+    }
+}
+
+/// Keeps carried sprites in their place as items come and go
+fn carried_layout_system(
+    q_player: Query<(&Inventory, &Children), With<Player>>,
+    mut q_carried: Query<(&mut Transform, &Product)>,
+) {
+    for (inventory, children) in q_player.iter() {
+        let mut unplaced: Vec<Entity> = children.iter().copied().collect();
+        for (slot, item) in inventory.items.iter().enumerate() {
+            let Some(item) = item else {
+                continue;
+            };
+            let found = unplaced.iter().position(|&child| {
+                q_carried
+                    .get(child)
+                    .is_ok_and(|(_, Product(carried))| carried == item)
+            });
+            let Some(index) = found else {
+                continue;
+            };
+
+            let child = unplaced.swap_remove(index);
+            let (mut transform, ..) = q_carried.get_mut(child).expect("Carried sprite");
+            *transform = carried_transform(inventory, slot);
+        }
+    }
+}
+
+/// The teller, and the customers lined up at it
+#[derive(SystemParam)]
+struct Counter<'w, 's> {
+    tellers: Query<'w, 's, (), With<Teller>>,
+    npcs: Query<'w, 's, &'static NPC>,
+    queue: ResMut<'w, CustomerQueue>,
+}
+
+impl Counter<'_, '_> {
+    /// Place in line of the first customer who wants `cake`
+    fn first_wanting(&self, cake: &CakeType) -> Option<usize> {
+        self.queue
+            .0
+            .iter()
+            .position(|&npc| self.npcs.get(npc).is_ok_and(|npc| npc.wants == *cake))
+    }
 }
 
-// NOTE: Could be a macro to autogen?
-impl Distribution<CakeType> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> CakeType {
-        let index: u8 = rng.gen_range(0..3);
-        match index {
-            0 => CakeType::Chocolate,
-            1 => CakeType::Fraisier,
-            2 => CakeType::Carrot,
-            _ => unreachable!(),
+fn teller_system(
+    mut commands: Commands,
+    mut events: EventReader<TriggerStay>,
+    interact: InteractCheck,
+    mut counter: Counter,
+    mut q_player: Query<(&mut Inventory, &Children), With<Player>>,
+    q_product: Query<&Product>,
+    mut score: ResMut<Score>,
+) {
+    for &TriggerStay { trigger, actor } in events.iter() {
+        let Ok((mut inventory, children)) = q_player.get_mut(actor) else {
+            continue;
+        };
+
+        if counter.tellers.contains(trigger) && interact.fires(trigger) {
+            // Serve the first customer in line who wants one of the carried cakes
+            let Some((cake, index)) = inventory.items.iter().flatten().find_map(|item| {
+                let Item::Cake(Baked::Cake(cake)) = item else {
+                    return None;
+                };
+                counter
+                    .first_wanting(cake)
+                    .map(|index| (item.clone(), index))
+            }) else {
+                continue;
+            };
+            let npc_e = counter.queue.0.remove(index);
+
+            score.0 += 1;
+
+            despawn_carried_product(&mut commands, children, &q_product, &cake);
+            commands.entity(npc_e).insert(Leaving);
+
+            inventory.take(&cake);
         }
     }
 }
 
-fn spawn_cake(
+const MAX_CUSTOMERS: usize = 4;
+const CUSTOMER_INTERVAL: Duration = Duration::from_secs(20);
+const CUSTOMER_SPEED: f32 = 120.0;
+const CUSTOMER_PATIENCE: Duration = Duration::from_secs(90);
+// Where customers come from and go back to, off screen
+const STREET_X: f32 = -750.0;
+
+/// Customers waiting at the teller, first in line first
+#[derive(Resource, Default)]
+struct CustomerQueue(Vec<Entity>);
+
+#[derive(Resource)]
+struct CustomerTimer(Timer);
+
+/// Customers walking out of the shop, served or not
+#[derive(Component)]
+struct Leaving;
+
+#[derive(Component)]
+struct PatienceBar;
+
+const PATIENCE_BAR_SIZE: Vec2 = Vec2::new(64.0, 8.0);
+
+fn spawn_customer(
     asset_server: &Res<AssetServer>,
+    recipes: &Recipes,
     commands: &mut Commands,
     position: Vec3,
     cake: CakeType,
-    parent: &Entity,
 ) -> Entity {
-    let color = {
-        match cake {
-            CakeType::Chocolate => asset_server.load("sprites/cake_chocolate.png"),
-            CakeType::Fraisier => asset_server.load("sprites/cake_strawberry.png"),
-            CakeType::Carrot => asset_server.load("sprites/cake_carrot.png"),
-        }
-    };
-
     let id = commands
         .spawn((
             SpriteBundle {
-                texture: color,
+                sprite: Sprite {
+                    color: Color::CYAN,
+                    custom_size: Some(Vec2::new(64.0, 64.0)),
+                    ..default()
+                },
                 transform: Transform::from_translation(position),
                 ..default()
             },
-            Cake(cake),
+            NPC {
+                wants: cake.clone(),
+                patience: Timer::new(CUSTOMER_PATIENCE, TimerMode::Once),
+            },
+            Velocity(Vec3::ZERO),
+            Acceleration(Vec3::ZERO),
+            Interpolated::new(position),
+            Collision,
+            CollisionBox(Vec3::new(64.0, 64.0, 0.0)),
             GameElement,
         ))
-        .set_parent(*parent)
         .id();
-    id
+
+    spawn_display_cake(
+        asset_server,
+        recipes,
+        commands,
+        Vec3::new(0.0, 60.0, 0.0),
+        cake,
+        &id,
+    );
+
+    commands
+        .spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::GREEN,
+                    custom_size: Some(PATIENCE_BAR_SIZE),
+                    ..default()
+                },
+                transform: Transform::from_xyz(0.0, 110.0, 1.0),
+                ..default()
+            },
+            PatienceBar,
+            GameElement,
+        ))
+        .set_parent(id);
+
+    id
+}
+
+fn patience_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut queue: ResMut<CustomerQueue>,
+    mut missed: ResMut<MissedCustomers>,
+    mut q_npc: Query<(Entity, &mut NPC), Without<Leaving>>,
+) {
+    for (npc_e, mut npc) in q_npc.iter_mut() {
+        npc.patience.tick(time.delta());
+
+        if npc.patience.finished() {
+            // Might have been served this very frame
+            if let Some(index) = queue.0.iter().position(|&e| e == npc_e) {
+                queue.0.remove(index);
+                missed.0 += 1;
+                commands.entity(npc_e).insert(Leaving);
+            }
+        }
+    }
+}
+
+fn patience_bar_system(
+    q_npc: Query<(&NPC, Option<&Leaving>)>,
+    mut q_bar: Query<(&Parent, &mut Sprite, &mut Visibility), With<PatienceBar>>,
+) {
+    for (parent, mut sprite, mut visibility) in q_bar.iter_mut() {
+        let Ok((npc, leaving)) = q_npc.get(parent.get()) else {
+            continue;
+        };
+
+        if leaving.is_some() {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+
+        let left = npc.patience.percent_left();
+        sprite.custom_size = Some(PATIENCE_BAR_SIZE * Vec2::new(left, 1.0));
+        sprite.color = Color::rgb(1.0 - left, left, 0.0);
+    }
+}
+
+fn customer_arrival_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut timer: ResMut<CustomerTimer>,
+    mut queue: ResMut<CustomerQueue>,
+    mut orders: Orders,
+    level: Res<CurrentLevel>,
+    sprites: ItemSprites,
+) {
+    timer.0.tick(time.delta());
+
+    if timer.0.just_finished() && queue.0.len() < MAX_CUSTOMERS {
+        let Some(cake) = orders.next() else {
+            return;
+        };
+        queue.0.push(spawn_customer(
+            &sprites.asset_server,
+            &sprites.recipes,
+            &mut commands,
+            Vec3::new(STREET_X, level.0.customers.y, 0.0),
+            cake,
+        ));
+    }
+}
+
+fn customer_movement_system(
+    mut commands: Commands,
+    time_step: Res<FixedTime>,
+    queue: Res<CustomerQueue>,
+    level: Res<CurrentLevel>,
+    mut q_npc: Query<(Entity, &mut Transform, Option<&Leaving>), With<NPC>>,
+) {
+    let step = CUSTOMER_SPEED * time_step.period.as_secs_f32();
+
+    for (npc, mut transform, leaving) in q_npc.iter_mut() {
+        if leaving.is_some() {
+            transform.translation.x -= step;
+            if transform.translation.x < STREET_X {
+                commands.entity(npc).despawn_recursive();
+            }
+        } else if let Some(index) = queue.0.iter().position(|&e| e == npc) {
+            // Walk up to our place in line
+            let diff = level.0.customer_slot(index).x - transform.translation.x;
+            transform.translation.x += diff.clamp(-step, step);
+        }
+    }
+}
+
+/// Anything that can be carried, from raw ingredients to finished cakes
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Item {
+    Ingredient(IngredientType),
+    /// Made at a station to go into another recipe
+    Intermediate(IntermediateType),
+    Cake(Baked),
+}
+
+impl Item {
+    /// Where to take it next, if it's a cake that isn't done yet
+    fn next_step(&self, recipes: &Recipes) -> Option<StationType> {
+        match self {
+            Item::Cake(baked) => baked.next_step(recipes),
+            Item::Ingredient(_) | Item::Intermediate(_) => None,
+        }
+    }
+
+    fn describe(&self, recipes: &Recipes) -> String {
+        match self {
+            Item::Ingredient(ing) => format!("{ing:?}"),
+            Item::Intermediate(product) => recipes
+                .0
+                .intermediate(product)
+                .map_or_else(|| product.0.clone(), |recipe| recipe.name.clone()),
+            Item::Cake(baked) => baked.describe(recipes),
+        }
+    }
+}
+
+/// What a cake is while going through its recipe's stations
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Baked {
+    /// Done with the first `steps_done` steps of its recipe
+    Unfinished {
+        cake: CakeType,
+        steps_done: usize,
+    },
+    Cake(CakeType),
+    /// Left in the oven for too long, only good for the bin
+    Burnt(CakeType),
+}
+
+impl Baked {
+    /// What a recipe turns into once `steps_done` of its steps are done
+    fn after_steps(recipe: &CakeRecipe, steps_done: usize) -> Self {
+        if steps_done < recipe.steps.len() {
+            Baked::Unfinished {
+                cake: recipe.cake.clone(),
+                steps_done,
+            }
+        } else {
+            Baked::Cake(recipe.cake.clone())
+        }
+    }
+
+    fn cake(&self) -> &CakeType {
+        match self {
+            Baked::Unfinished { cake, .. } | Baked::Cake(cake) | Baked::Burnt(cake) => cake,
+        }
+    }
+
+    /// Where to take it next, if it isn't done yet
+    fn next_step(&self, recipes: &Recipes) -> Option<StationType> {
+        match self {
+            Baked::Unfinished { cake, steps_done } => recipes
+                .get(cake)
+                .and_then(|recipe| recipe.steps.get(*steps_done).copied()),
+            Baked::Cake(_) | Baked::Burnt(_) => None,
+        }
+    }
+
+    fn describe(&self, recipes: &Recipes) -> String {
+        let name = recipes
+            .get(self.cake())
+            .map_or("cake", |recipe| recipe.name.as_str());
+        match (self, self.next_step(recipes)) {
+            (Baked::Burnt(_), _) => format!("burnt {name}"),
+            (_, Some(station)) => format!("{name} (for the {})", station.name()),
+            _ => name.to_string(),
+        }
+    }
+}
+
+#[derive(Component)]
+struct Inventory {
+    items: Vec<Option<Item>>,
+    /// Slot to drop from
+    selected: usize,
+}
+
+impl Inventory {
+    fn new(capacity: usize) -> Self {
+        Self {
+            items: vec![None; capacity],
+            selected: 0,
+        }
+    }
+
+    fn carried(&self) -> Vec<Item> {
+        self.items.iter().flatten().cloned().collect()
+    }
+
+    fn has_room(&self) -> bool {
+        self.items.contains(&None)
+    }
+
+    /// Puts `item` in the first free slot and returns it, if there is one
+    fn add(&mut self, item: Item) -> Option<usize> {
+        let slot = self.items.iter().position(Option::is_none)?;
+        self.items[slot] = Some(item);
+        Some(slot)
+    }
+
+    /// Removes one `item`, keeping the remaining items packed at the front
+    fn take(&mut self, item: &Item) -> bool {
+        let Some(index) = self
+            .items
+            .iter()
+            .position(|slot| slot.as_ref() == Some(item))
+        else {
+            return false;
+        };
+        self.items[index..].rotate_left(1);
+        *self.items.last_mut().unwrap() = None;
+        true
+    }
+
+    /// A carried cake that goes to `station` next
+    fn next_for(&self, station: StationType, recipes: &Recipes) -> Option<&Item> {
+        self.items
+            .iter()
+            .flatten()
+            .find(|item| item.next_step(recipes) == Some(station))
+    }
+}
+
+#[derive(Component)]
+struct CookingTable(StationType);
+
+/// How long a baked cake waits in the oven before burning
+const BURNING_TIME: Duration = Duration::from_secs(12);
+
+/// What's going on at a cooking table
+#[derive(Component, Default)]
+enum Batch {
+    #[default]
+    Empty,
+    Working {
+        product: Item,
+        timer: Timer,
+    },
+    /// Waiting to be picked up, until `timer` runs out and it burns
+    Ready {
+        product: Item,
+        timer: Option<Timer>,
+    },
+}
+
+#[derive(Component)]
+struct BatchBar;
+
+const BATCH_BAR_SIZE: Vec2 = Vec2::new(120.0, 10.0);
+
+fn batch_system(time: Res<Time>, mut q_table: Query<(&CookingTable, &mut Batch)>) {
+    for (CookingTable(station), mut batch) in q_table.iter_mut() {
+        match &mut *batch {
+            Batch::Working { product, timer } => {
+                if timer.tick(time.delta()).finished() {
+                    // Only cakes burn, intermediates wait for as long as needed
+                    let burns = station.burns() && matches!(product, Item::Cake(_));
+                    *batch = Batch::Ready {
+                        product: product.clone(),
+                        timer: burns.then(|| Timer::new(BURNING_TIME, TimerMode::Once)),
+                    };
+                }
+            }
+            Batch::Ready {
+                product: Item::Cake(baked),
+                timer: Some(timer),
+            } => {
+                if timer.tick(time.delta()).finished() {
+                    *batch = Batch::Ready {
+                        product: Item::Cake(Baked::Burnt(baked.cake().clone())),
+                        timer: None,
+                    };
+                }
+            }
+            Batch::Empty | Batch::Ready { .. } => {}
+        }
+    }
+}
+
+fn batch_bar_system(
+    q_batch: Query<&Batch>,
+    mut q_bar: Query<(&Parent, &mut Sprite, &mut Visibility), With<BatchBar>>,
+) {
+    for (parent, mut sprite, mut visibility) in q_bar.iter_mut() {
+        let Ok(batch) = q_batch.get(parent.get()) else {
+            continue;
+        };
+
+        // Fills up while working, then empties as the cake is about to burn
+        let (fill, color) = match batch {
+            Batch::Empty => {
+                *visibility = Visibility::Hidden;
+                continue;
+            }
+            Batch::Working { timer, .. } => (timer.percent(), Color::ORANGE),
+            Batch::Ready {
+                timer: Some(timer), ..
+            } => {
+                let left = timer.percent_left();
+                (left, Color::rgb(1.0 - left, left, 0.0))
+            }
+            Batch::Ready {
+                product: Item::Cake(Baked::Burnt(_)),
+                ..
+            } => (1.0, Color::DARK_GRAY),
+            Batch::Ready { .. } => (1.0, Color::GREEN),
+        };
+
+        *visibility = Visibility::Visible;
+        sprite.custom_size = Some(BATCH_BAR_SIZE * Vec2::new(fill, 1.0));
+        sprite.color = color;
+    }
+}
+
+/// Sprite of an item, carried by the player or lying on the floor
+#[derive(Component)]
+struct Product(Item);
+
+/// Id of a cake in the recipe book
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct CakeType(String);
+
+/// Id of an intermediate in the recipe book
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct IntermediateType(String);
+
+fn cake_texture(
+    asset_server: &Res<AssetServer>,
+    recipes: &Recipes,
+    cake: &CakeType,
+) -> Handle<Image> {
+    match recipes.get(cake) {
+        Some(recipe) => asset_server.load(recipe.sprite.as_str()),
+        None => {
+            warn!("No recipe for {:?}", cake);
+            Handle::default()
+        }
+    }
+}
+
+fn item_texture(asset_server: &Res<AssetServer>, recipes: &Recipes, item: &Item) -> Handle<Image> {
+    match item {
+        Item::Ingredient(ing) => ingredient_texture(asset_server, ing),
+        Item::Intermediate(product) => match recipes.0.intermediate(product) {
+            Some(recipe) => asset_server.load(recipe.sprite.as_str()),
+            None => {
+                warn!("No recipe for {:?}", product);
+                Handle::default()
+            }
+        },
+        Item::Cake(baked) => cake_texture(asset_server, recipes, baked.cake()),
+    }
+}
+
+/// Tint and scale of a carried item
+fn product_look(item: &Item) -> (Color, f32) {
+    match item {
+        Item::Ingredient(_) | Item::Intermediate(_) => (Color::WHITE, 0.5),
+        Item::Cake(Baked::Unfinished { .. }) => (Color::rgba(1.0, 1.0, 1.0, 0.5), 0.7),
+        Item::Cake(Baked::Cake(_)) => (Color::WHITE, 1.0),
+        Item::Cake(Baked::Burnt(_)) => (Color::rgb(0.2, 0.15, 0.1), 1.0),
+    }
+}
+
+/// Spawns the sprite of a carried `item`
+fn spawn_product(
+    asset_server: &Res<AssetServer>,
+    recipes: &Recipes,
+    commands: &mut Commands,
+    item: &Item,
+    transform: Transform,
+    parent: &Entity,
+) -> Entity {
+    let (color, _) = product_look(item);
+
+    commands
+        .spawn((
+            SpriteBundle {
+                texture: item_texture(asset_server, recipes, item),
+                sprite: Sprite { color, ..default() },
+                transform,
+                ..default()
+            },
+            Product(item.clone()),
+            GameElement,
+        ))
+        .set_parent(*parent)
+        .id()
+}
+
+/// Despawns the sprite of one carried `item`
+fn despawn_carried_product(
+    commands: &mut Commands,
+    children: &Children,
+    q_product: &Query<&Product>,
+    item: &Item,
+) {
+    let carried = children
+        .iter()
+        .find(|&&child| q_product.get(child).is_ok_and(|Product(i)| i == item));
+    if let Some(&child) = carried {
+        commands.entity(child).despawn_recursive();
+    }
 }
 
 fn spawn_display_cake(
     asset_server: &Res<AssetServer>,
+    recipes: &Recipes,
     commands: &mut Commands,
     position: Vec3,
     cake: CakeType,
     parent: &Entity,
 ) -> Entity {
-    let color = {
-        match cake {
-            CakeType::Chocolate => asset_server.load("sprites/cake_chocolate.png"),
-            CakeType::Fraisier => asset_server.load("sprites/cake_strawberry.png"),
-            CakeType::Carrot => asset_server.load("sprites/cake_carrot.png"),
-        }
-    };
+    let color = cake_texture(asset_server, recipes, &cake);
 
     let id = commands
         .spawn((
@@ -872,171 +2234,1113 @@ fn spawn_display_cake(
 
 fn cooking_table_system(
     mut commands: Commands,
-    mut q: Query<(&mut Transform, &TriggerBox), (With<CookingTable>, Without<Player>)>,
-    mut q_player: Query<
-        (
-            Entity,
-            &Transform,
-            &CollisionBox,
-            &mut Inventory,
-            Option<&Children>,
-        ),
-        With<Player>,
-    >,
-    _q_ingredients: Query<(Entity, &Ingredient)>,
-    recipes: Res<Recipes>,
-    asset_server: Res<AssetServer>,
+    mut events: EventReader<TriggerStay>,
+    interact: InteractCheck,
+    mut q_table: Query<(&CookingTable, &mut Batch)>,
+    mut q_player: Query<(&mut Inventory, Option<&Children>), With<Player>>,
+    q_product: Query<&Product>,
+    sprites: ItemSprites,
 ) {
-    let (player, player_trans, player_sprite, mut inventory, _children) =
-        q_player.get_single_mut().expect("Always a player");
-
-    for (transform, sprite) in q.iter_mut() {
-        //info!("checking collisions");
-        let collision = collide(
-            player_trans.translation,
-            player_sprite.0.truncate(),
-            transform.translation,
-            sprite.0.truncate(),
-        );
+    let recipes = &sprites.recipes;
 
-        if collision.is_some() {
-            let cake = 'cake: {
-                'recipes: for (Recipe { ingredients }, v) in recipes.0.iter() {
-                    for ing in inventory.items.iter() {
-                        if let Some(ing) = ing {
-                            if !ingredients.contains(ing) {
-                                // Check next recipe
-                                continue 'recipes;
-                            }
-                        } else {
-                            // Got None, missing ingredients
-                            return;
+    for &TriggerStay { trigger, actor } in events.iter() {
+        let (Ok((CookingTable(station), mut batch)), Ok((mut inventory, children))) =
+            (q_table.get_mut(trigger), q_player.get_mut(actor))
+        else {
+            continue;
+        };
+
+        if !interact.fires(trigger) {
+            continue;
+        }
+
+        match &*batch {
+            Batch::Empty => {
+                // Carry on with a cake started elsewhere before starting anything new
+                let product = if let Some(item) = inventory.next_for(*station, recipes).cloned() {
+                    let Item::Cake(Baked::Unfinished { cake, steps_done }) = &item else {
+                        unreachable!("Only unfinished cakes have a next step");
+                    };
+                    let cake_recipe = recipes.get(cake).expect("Cake has a recipe");
+
+                    inventory.take(&item);
+                    if let Some(children) = children {
+                        despawn_carried_product(&mut commands, children, &q_product, &item);
+                    }
+                    Item::Cake(Baked::after_steps(cake_recipe, steps_done + 1))
+                } else if let Some(craft) =
+                    recipe::find_recipe(&recipes.0, &inventory.carried(), *station)
+                {
+                    // Use up the items of the recipe, keep the rest
+                    let mut carried: Vec<Entity> = children
+                        .map(|children| children.to_vec())
+                        .unwrap_or_default();
+                    for item in craft.recipe().ingredients() {
+                        inventory.take(item);
+                        let found = carried.iter().position(|&child| {
+                            q_product
+                                .get(child)
+                                .is_ok_and(|Product(carried)| carried == item)
+                        });
+                        if let Some(index) = found {
+                            commands
+                                .entity(carried.swap_remove(index))
+                                .despawn_recursive();
                         }
                     }
-                    break 'cake Some(v.clone());
-                }
-                None
+
+                    match craft {
+                        Craft::Intermediate(intermediate) => {
+                            Item::Intermediate(intermediate.product.clone())
+                        }
+                        Craft::Cake(cake_recipe) => Item::Cake(Baked::after_steps(cake_recipe, 1)),
+                    }
+                } else {
+                    continue;
+                };
+
+                *batch = Batch::Working {
+                    product,
+                    timer: Timer::new(station.duration(), TimerMode::Once),
+                };
+            }
+            Batch::Ready { product, .. } => {
+                let Some(slot) = inventory.add(product.clone()) else {
+                    // Hands full
+                    continue;
+                };
+                let transform = carried_transform(&inventory, slot);
+                spawn_product(
+                    &sprites.asset_server,
+                    recipes,
+                    &mut commands,
+                    product,
+                    transform,
+                    &actor,
+                );
+                *batch = Batch::Empty;
+            }
+            Batch::Working { .. } => {}
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct Recipes(RecipeBook);
+
+impl Recipes {
+    fn get(&self, cake: &CakeType) -> Option<&CakeRecipe> {
+        self.0.cake(cake)
+    }
+
+    /// Whether every station `cake` goes through is in `level`
+    fn can_make(&self, level: &Level, cake: &CakeType) -> bool {
+        self.get(cake)
+            .is_some_and(|recipe| level.can_make(&self.0.stations_for(recipe)))
+    }
+}
+
+/// What spawning the sprites of items and customers takes
+#[derive(SystemParam)]
+struct ItemSprites<'w> {
+    recipes: Res<'w, Recipes>,
+    asset_server: Res<'w, AssetServer>,
+}
+
+// SEED
+
+/// Random seeds are kept short enough to read out and type back in
+const MAX_RANDOM_SEED: u64 = 1_000_000;
+
+/// What every random decision of a shift follows, so the same seed plays the same shift
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug)]
+struct Seed(u64);
+
+impl Seed {
+    /// From `--seed <number>` on the command line, random otherwise
+    fn from_args() -> Self {
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = match arg.strip_prefix("--seed") {
+                Some("") => args.next(),
+                Some(value) => match value.strip_prefix('=') {
+                    Some(value) => Some(value.to_string()),
+                    None => continue,
+                },
+                None => continue,
             };
+            match value.as_deref().map(u64::from_str) {
+                Some(Ok(seed)) => return Seed(seed),
+                _ => warn!("--seed needs a number, got {:?}", value),
+            }
+        }
+        Self::random()
+    }
+
+    fn random() -> Self {
+        Seed(rand::thread_rng().gen_range(0..MAX_RANDOM_SEED))
+    }
 
-            if let Some(cake) = cake {
-                // We have cake!
+    /// Same for everyone on the same day, for daily challenges
+    fn daily() -> Self {
+        Seed(unix_time() / (24 * 60 * 60))
+    }
+}
 
-                // Clear all ingredients
-                for item in inventory.items.iter_mut() {
-                    if let Some(item) = item {
-                        spawn_ingredient(&asset_server, &mut commands, item.clone());
-                    }
-                    *item = None;
-                }
-                // Clear what player is carrying
-                commands.entity(player).despawn_descendants();
+/// Today's seed, worked out once at startup
+#[derive(Resource)]
+struct DailySeed(Seed);
+
+/// Seconds since the Unix epoch
+#[cfg(not(target_arch = "wasm32"))]
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
 
-                // Spawn the cake
-                spawn_cake(
-                    &asset_server,
-                    &mut commands,
-                    Vec3::new(0.0, 40.0, 0.0),
-                    cake.clone(),
-                    &player,
+/// Seconds since the Unix epoch, `SystemTime` panics in the browser
+#[cfg(target_arch = "wasm32")]
+fn unix_time() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+/// Source of every random decision, reseeded from [`Seed`] at the start of each shift
+///
+/// ChaCha8 rather than `StdRng`, whose algorithm may change between rand releases
+/// and platforms, so a seed plays the same shift everywhere
+#[derive(Resource)]
+struct GameRng(ChaCha8Rng);
+
+impl GameRng {
+    fn new(seed: Seed) -> Self {
+        Self(ChaCha8Rng::seed_from_u64(seed.0))
+    }
+}
+
+// MENU
+
+/// How much more often the special of the day is ordered
+const SPECIAL_WEIGHT: u32 = 3;
+
+/// What customers of this shift order
+#[derive(Resource)]
+struct Menu {
+    cakes: Vec<(CakeType, u32)>,
+    special: Option<CakeType>,
+    /// Cakes to order before any of them comes twice, so each one is asked for at least once
+    not_ordered: Vec<CakeType>,
+}
+
+impl Menu {
+    /// Cakes on the menu of `level` and their weights, whether they can be made or not
+    fn listed(level: &Level, recipes: &Recipes) -> Vec<(CakeType, u32)> {
+        if level.menu.is_empty() {
+            recipes
+                .0
+                .cakes
+                .iter()
+                .map(|recipe| (recipe.cake.clone(), 1))
+                .collect()
+        } else {
+            level
+                .menu
+                .iter()
+                .map(|item| (item.cake.clone(), item.weight))
+                .collect()
+        }
+    }
+
+    /// Whether customers of `level` have anything to order
+    fn has_orders(level: &Level, recipes: &Recipes) -> bool {
+        Self::listed(level, recipes)
+            .iter()
+            .any(|(cake, _)| recipes.can_make(level, cake))
+    }
+
+    fn new(level: &Level, recipes: &Recipes, rng: &mut ChaCha8Rng) -> Self {
+        let mut cakes = Vec::new();
+        for (cake, weight) in Self::listed(level, recipes) {
+            if recipes.can_make(level, &cake) {
+                cakes.push((cake, weight));
+            } else {
+                warn!(
+                    "{:?} is on the menu of {} but can't be made",
+                    cake, level.name
                 );
+            }
+        }
+
+        let special = match &level.special {
+            Some(cake) if cakes.iter().any(|(other, _)| other == cake) => Some(cake.clone()),
+            Some(cake) => {
+                warn!("Special {:?} is not on the menu of {}", cake, level.name);
+                None
+            }
+            None => cakes.choose(rng).map(|(cake, _)| cake.clone()),
+        };
+        for (cake, weight) in cakes.iter_mut() {
+            if Some(&*cake) == special.as_ref() {
+                *weight *= SPECIAL_WEIGHT;
+            }
+        }
+
+        Self {
+            not_ordered: cakes.iter().map(|(cake, _)| cake.clone()).collect(),
+            cakes,
+            special,
+        }
+    }
+
+    /// Weighted pick, among the cakes not ordered yet until they all were
+    fn next_order(&mut self, rng: &mut ChaCha8Rng) -> Option<CakeType> {
+        let candidates: Vec<&(CakeType, u32)> = self
+            .cakes
+            .iter()
+            .filter(|(cake, _)| self.not_ordered.is_empty() || self.not_ordered.contains(cake))
+            .collect();
+        let (cake, _) = candidates
+            .choose_weighted(rng, |(_, weight)| *weight)
+            .ok()?;
+        let cake = cake.clone();
+
+        self.not_ordered.retain(|other| *other != cake);
+        Some(cake)
+    }
+}
+
+/// The menu together with the randomness it is drawn with
+#[derive(SystemParam)]
+struct Orders<'w> {
+    menu: ResMut<'w, Menu>,
+    rng: ResMut<'w, GameRng>,
+}
+
+impl Orders<'_> {
+    fn next(&mut self) -> Option<CakeType> {
+        self.menu.next_order(&mut self.rng.0)
+    }
+}
+
+#[derive(Resource)]
+struct RecipeBookHandle(Handle<RecipeBook>);
+
+fn load_recipes_system(
+    mut events: EventReader<AssetEvent<RecipeBook>>,
+    books: Res<Assets<RecipeBook>>,
+    handle: Res<RecipeBookHandle>,
+    mut recipes: ResMut<Recipes>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: h } | AssetEvent::Modified { handle: h }
+                if *h == handle.0 =>
+            {
+                let book = books.get(h).expect("Recipe book was just loaded");
+                for recipe in book.intermediates.iter() {
+                    info!(
+                        "Adding {} {:?} with {:?}",
+                        recipe.name, recipe.product, recipe.recipe
+                    );
+                }
+                for recipe in book.cakes.iter() {
+                    info!(
+                        "Adding {} {:?} with {:?}",
+                        recipe.name, recipe.cake, recipe.recipe
+                    );
+                }
+                recipes.0 = book.clone();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Component)]
+struct Bin;
+
+fn bin_system(
+    mut commands: Commands,
+    mut events: EventReader<TriggerStay>,
+    q_bin: Query<(), With<Bin>>,
+    mut q_player: Query<&mut Inventory, With<Player>>,
+    mut q_stock: Query<&mut Stock>,
+    actions: Res<ActionState>,
+) {
+    for &TriggerStay { trigger, actor } in events.iter() {
+        let Ok(mut inventory) = q_player.get_mut(actor) else {
+            continue;
+        };
+
+        if q_bin.contains(trigger) && actions.just_pressed(Action::Drop) {
+            for item in inventory.items.iter_mut() {
+                // Unused ingredients go back to a dispenser of theirs, the emptiest
+                // when a kitchen has several
+                if let Some(Item::Ingredient(ing)) = item {
+                    if let Some(mut stock) = q_stock
+                        .iter_mut()
+                        .filter(|stock| stock.ingredient == *ing)
+                        .min_by_key(|stock| stock.count)
+                    {
+                        stock.count += 1;
+                    }
+                }
+                *item = None;
+            }
+
+            commands.entity(actor).despawn_descendants();
+        }
+    }
+}
+
+/// Spawn a new bomb in set intervals of time
+fn level_timer_system(
+    time: Res<Time>,
+    mut timer: ResMut<LevelTimer>,
+    mut app_state: ResMut<NextState<GameState>>,
+) {
+    // tick the timer
+    timer.0.tick(time.delta());
+
+    if timer.0.finished() {
+        app_state.set(GameState::EndScreen);
+    }
+}
+
+// MAIN MENU
+
+#[derive(Component)]
+struct TitleMenu;
+
+#[derive(Component)]
+enum TitleMenuAction {
+    NewGame,
+    LevelSelect,
+    Controls,
+    /// Rebinds one slot of an action
+    Rebind(Action, usize),
+    ResetControls,
+    ToggleInteractMode,
+    EnterSeed,
+    DailySeed,
+    NewSeed,
+    Play(usize),
+    NextLevel,
+    Resume,
+    Restart,
+    MainMenu,
+    Quit,
+}
+
+fn setup_title_menu(mut commands: Commands, campaign: Res<Campaign>, settings: Res<Settings>) {
+    let mut records: Vec<&LevelRecord> = LEVELS
+        .iter()
+        .filter_map(|path| campaign.levels.get(*path))
+        .collect();
+    records.retain(|record| record.high_score > 0);
+    let high_scores = records
+        .iter()
+        .map(|record| format!("{}: {} cakes", record.name, record.high_score))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let bindings = &settings.bindings;
+    let mut controls = format!(
+        "Move: {} / {}\nJump: {}\nDrop through platforms, clear inventory at the bin: {}\nSelect an item: {}\nPut it down: {}\nPause: {}",
+        bindings.describe(Action::MoveLeft),
+        bindings.describe(Action::MoveRight),
+        bindings.describe(Action::Jump),
+        bindings.describe(Action::Drop),
+        bindings.describe(Action::NextSlot),
+        bindings.describe(Action::DropItem),
+        bindings.describe(Action::Pause),
+    );
+    if settings.interact_mode {
+        controls += &format!("\nUse stations: {}", bindings.describe(Action::Interact));
+    }
+
+    // Common style for all buttons on the screen
+    let button_style = Style {
+        width: Val::Px(250.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: Color::WHITE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            TitleMenu,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Px(40.0),
+			position_type: PositionType::Absolute,
+			bottom: Val::Px(0.0),
+                        ..default()
+                    },
+                    background_color: Color::CRIMSON.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(r#"    Music "Paris" by Sakura Girl (https://officialsakuragirl.wixsite.com/official)"#,
+                            TextStyle {
+                                font_size: 20.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        )
+                        .with_style(Style { ..default() }),
+                        // Because this is a distinct label widget and
+                        // not button/list item text, this is necessary
+                        // for accessibility to treat the text accordingly.
+                        Label,
+                    ));
+                });
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::CRIMSON.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(
+                            "Truly Teeny Tiny Bakery",
+                            TextStyle {
+                                font_size: 60.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        )
+                        .with_style(Style {
+                            margin: UiRect::all(Val::Px(20.0)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        }),
+                        // Because this is a distinct label widget and
+                        // not button/list item text, this is necessary
+                        // for accessibility to treat the text accordingly.
+                        Label,
+                    ));
+
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            TitleMenuAction::LevelSelect,
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(
+                                "Play",
+                                button_text_style.clone(),
+                            ));
+                        });
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            TitleMenuAction::Controls,
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(
+                                "Controls",
+                                button_text_style.clone(),
+                            ));
+                        });
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            TitleMenuAction::Quit,
+                        ))
+                        .with_children(|parent| {
+                            parent
+                                .spawn(TextBundle::from_section("Quit", button_text_style.clone()));
+                        });
+
+
+		                        parent.spawn((
+                        TextBundle::from_section(
+                            controls,
+                            TextStyle {
+                                font_size: 20.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        )
+                        .with_style(Style {
+                            margin: UiRect::all(Val::Px(20.0)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        }),
+                        // Because this is a distinct label widget and
+                        // not button/list item text, this is necessary
+                        // for accessibility to treat the text accordingly.
+                        Label,
+                    ));
+
+                    if !high_scores.is_empty() {
+                        parent.spawn((
+                            TextBundle::from_section(
+                                format!("High scores\n{high_scores}"),
+                                TextStyle {
+                                    font_size: 25.0,
+                                    color: Color::WHITE,
+                                    ..default()
+                                },
+                            )
+                            .with_style(Style {
+                                margin: UiRect::all(Val::Px(20.0)),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                ..default()
+                            }),
+                            Label,
+                        ));
+                    }
+                });
+        });
+}
+
+fn title_menu(keyboard_input: Res<Input<KeyCode>>, mut app_state: ResMut<NextState<GameState>>) {}
+
+fn title_menu_action(
+    interaction_query: Query<
+        (&Interaction, &TitleMenuAction),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut app_exit_events: EventWriter<AppExit>,
+    mut app_state: ResMut<NextState<GameState>>,
+    mut pause_state: ResMut<NextState<PauseState>>,
+    mut shift: NextShift,
+    mut settings: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    for (interaction, menu_button_action) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            let level = match menu_button_action {
+                TitleMenuAction::NewGame => shift.selected.0,
+                TitleMenuAction::Play(level) => *level,
+                TitleMenuAction::NextLevel => shift.selected.0 + 1,
+                TitleMenuAction::LevelSelect => {
+                    app_state.set(GameState::LevelSelect);
+                    continue;
+                }
+                TitleMenuAction::Controls => {
+                    app_state.set(GameState::Controls);
+                    continue;
+                }
+                TitleMenuAction::Rebind(action, slot) => {
+                    rebinding.0 = Some((*action, *slot));
+                    continue;
+                }
+                TitleMenuAction::ResetControls => {
+                    rebinding.0 = None;
+                    settings.bindings = Bindings::default();
+                    save::save(SETTINGS_SAVE, &*settings);
+                    continue;
+                }
+                TitleMenuAction::ToggleInteractMode => {
+                    settings.interact_mode = !settings.interact_mode;
+                    save::save(SETTINGS_SAVE, &*settings);
+                    continue;
+                }
+                TitleMenuAction::EnterSeed => {
+                    shift.seed_entry.0 = Some(String::new());
+                    continue;
+                }
+                TitleMenuAction::DailySeed => {
+                    shift.seed_entry.0 = None;
+                    *shift.seed = shift.daily.0;
+                    continue;
+                }
+                TitleMenuAction::NewSeed => {
+                    shift.seed_entry.0 = None;
+                    *shift.seed = Seed::random();
+                    continue;
+                }
+                TitleMenuAction::Resume => {
+                    pause_state.set(PauseState::Running);
+                    continue;
+                }
+                TitleMenuAction::Restart => {
+                    pause_state.set(PauseState::Running);
+                    app_state.set(GameState::Restarting);
+                    continue;
+                }
+                TitleMenuAction::MainMenu => {
+                    pause_state.set(PauseState::Running);
+                    app_state.set(GameState::MainMenu);
+                    continue;
+                }
+                TitleMenuAction::Quit => {
+                    app_exit_events.send(AppExit);
+                    continue;
+                }
+            };
+
+            shift.finish_seed_entry();
+            if shift.select(level) {
+                app_state.set(GameState::InGame);
+            }
+        }
+    }
+}
+
+const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
+const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
+
+fn button_system(
+    mut interaction_query: Query<
+        (&Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<Button>),
+    >,
+) {
+    for (interaction, mut color) in &mut interaction_query {
+        *color = match *interaction {
+            Interaction::Pressed => PRESSED_BUTTON.into(),
+            Interaction::Hovered => HOVERED_BUTTON.into(),
+            Interaction::None => NORMAL_BUTTON.into(),
+        }
+    }
+}
+
+// LEVEL SELECT
+
+const CAMPAIGN_SAVE: &str = "campaign";
+
+/// Stars needed for the bigger apron, which has one more inventory slot
+const APRON_STARS: u32 = 6;
+
+#[derive(Default, Serialize, Deserialize)]
+struct LevelRecord {
+    name: String,
+    high_score: usize,
+    stars: u8,
+}
+
+/// Best results on each level, keyed by level path and kept between runs
+#[derive(Resource, Default, Serialize, Deserialize)]
+struct Campaign {
+    levels: HashMap<String, LevelRecord>,
+}
+
+impl Campaign {
+    fn get(&self, level: usize) -> Option<&LevelRecord> {
+        LEVELS.get(level).and_then(|path| self.levels.get(*path))
+    }
+
+    fn stars(&self, level: usize) -> u8 {
+        self.get(level).map_or(0, |record| record.stars)
+    }
+
+    /// One more inventory slot once enough stars are earned overall
+    fn bonus_slots(&self) -> usize {
+        let stars: u32 = self.levels.values().map(|record| record.stars as u32).sum();
+        usize::from(stars >= APRON_STARS)
+    }
+
+    /// Levels unlock one after the other by clearing the previous one
+    fn is_unlocked(&self, level: usize) -> bool {
+        level == 0 || self.stars(level - 1) > 0
+    }
+
+    /// Returns whether `score` is a new high score
+    fn record(&mut self, level: usize, name: &str, score: usize, stars: u8) -> bool {
+        let record = self.levels.entry(LEVELS[level].to_string()).or_default();
+        record.name = name.to_string();
+        record.stars = record.stars.max(stars);
+
+        let new_record = score > record.high_score;
+        if new_record {
+            record.high_score = score;
+        }
+        new_record
+    }
+}
+
+fn format_stars(stars: u8) -> String {
+    format!(
+        "{}{}",
+        "*".repeat(stars as usize),
+        "-".repeat(3 - stars as usize)
+    )
+}
+
+/// Records the result of the shift, returns whether it's a new high score
+fn update_campaign(
+    score: Res<Score>,
+    level: Res<CurrentLevel>,
+    selected: Res<SelectedLevel>,
+    mut campaign: ResMut<Campaign>,
+) -> bool {
+    let stars = level.0.stars_for(score.0);
+    info!("{} cleared with {} stars", level.0.name, stars);
+    let new_record = campaign.record(selected.0, &level.0.name, score.0, stars);
+    save::save(CAMPAIGN_SAVE, &*campaign);
+    new_record
+}
+
+#[derive(Component)]
+struct LevelSelectMenu;
+
+fn setup_level_select(
+    mut commands: Commands,
+    levels: Res<Assets<Level>>,
+    level_handles: Res<LevelHandles>,
+    campaign: Res<Campaign>,
+    seed: Res<Seed>,
+    daily: Res<DailySeed>,
+    mut seed_entry: ResMut<SeedEntry>,
+) {
+    seed_entry.0 = None;
+
+    // Common style for all buttons on the screen
+    let button_style = Style {
+        width: Val::Px(600.0),
+        height: Val::Px(80.0),
+        margin: UiRect::all(Val::Px(10.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 25.0,
+        color: Color::WHITE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            LevelSelectMenu,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::CRIMSON.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(
+                            "Choose your kitchen",
+                            TextStyle {
+                                font_size: 50.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        )
+                        .with_style(Style {
+                            margin: UiRect::all(Val::Px(20.0)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        }),
+                        // Because this is a distinct label widget and
+                        // not button/list item text, this is necessary
+                        // for accessibility to treat the text accordingly.
+                        Label,
+                    ));
+
+                    for index in 0..LEVELS.len() {
+                        let text = level_button_text(index, &levels, &level_handles, &campaign);
+
+                        let mut button = parent.spawn(ButtonBundle {
+                            style: button_style.clone(),
+                            background_color: NORMAL_BUTTON.into(),
+                            ..default()
+                        });
+                        if campaign.is_unlocked(index) {
+                            button.insert(TitleMenuAction::Play(index));
+                        }
+                        button.with_children(|parent| {
+                            parent.spawn((
+                                TextBundle::from_section(text, button_text_style.clone()),
+                                LevelButtonText(index),
+                            ));
+                        });
+                    }
+
+                    parent
+                        .spawn(NodeBundle {
+                            style: Style {
+                                align_items: AlignItems::Center,
+                                ..default()
+                            },
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            let text = seed_text(*seed, daily.0, &seed_entry);
+                            for (action, label, width) in [
+                                (TitleMenuAction::EnterSeed, text.as_str(), 280.0),
+                                (TitleMenuAction::DailySeed, "Daily", 150.0),
+                                (TitleMenuAction::NewSeed, "New seed", 150.0),
+                            ] {
+                                let is_seed = matches!(action, TitleMenuAction::EnterSeed);
+                                parent
+                                    .spawn((
+                                        ButtonBundle {
+                                            style: Style {
+                                                width: Val::Px(width),
+                                                height: Val::Px(50.0),
+                                                margin: UiRect::all(Val::Px(10.0)),
+                                                justify_content: JustifyContent::Center,
+                                                align_items: AlignItems::Center,
+                                                ..default()
+                                            },
+                                            background_color: NORMAL_BUTTON.into(),
+                                            ..default()
+                                        },
+                                        action,
+                                    ))
+                                    .with_children(|parent| {
+                                        let mut text = parent.spawn(TextBundle::from_section(
+                                            label,
+                                            button_text_style.clone(),
+                                        ));
+                                        if is_seed {
+                                            text.insert(SeedText);
+                                        }
+                                    });
+                            }
+                        });
+
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            TitleMenuAction::MainMenu,
+                        ))
+                        .with_children(|parent| {
+                            parent
+                                .spawn(TextBundle::from_section("Back", button_text_style.clone()));
+                        });
+                });
+        });
+}
 
-                // Add to inventory
-                inventory.cake = Some(cake);
-            }
+#[derive(Component)]
+struct LevelButtonText(usize);
+
+fn level_button_text(
+    index: usize,
+    levels: &Assets<Level>,
+    level_handles: &LevelHandles,
+    campaign: &Campaign,
+) -> String {
+    match level_handles.get(levels, index) {
+        Some(level) if campaign.is_unlocked(index) => {
+            let secs = level.time.as_secs();
+            format!(
+                "{}. {}  {}\nSell {} cakes in {:0>2}:{:0>2}",
+                index + 1,
+                level.name,
+                format_stars(campaign.stars(index)),
+                level.goal(),
+                secs / 60,
+                secs % 60
+            )
         }
+        Some(_) => format!("{}. Locked", index + 1),
+        None => format!("{}. Loading...", index + 1),
     }
 }
 
-#[derive(Resource)]
-struct Recipes(HashMap<Recipe, CakeType>);
-
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct Recipe {
-    ingredients: Vec<IngredientType>,
-}
+/// Fills in levels that were still loading when the menu opened
+fn update_level_buttons(
+    mut events: EventReader<AssetEvent<Level>>,
+    levels: Res<Assets<Level>>,
+    level_handles: Res<LevelHandles>,
+    campaign: Res<Campaign>,
+    mut query: Query<(&mut Text, &LevelButtonText)>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
 
-impl Recipe {
-    fn new(ingredients: &[IngredientType]) -> Self {
-        Self {
-            ingredients: Vec::from(ingredients),
-        }
+    for (mut text, LevelButtonText(index)) in query.iter_mut() {
+        text.sections[0].value = level_button_text(*index, &levels, &level_handles, &campaign);
     }
 }
 
+/// Longest seed that can be typed in, any number this long fits
+const MAX_SEED_DIGITS: usize = 19;
+
+/// Digits typed so far while entering a seed by hand
+#[derive(Resource, Default)]
+struct SeedEntry(Option<String>);
+
 #[derive(Component)]
-struct Bin;
+struct SeedText;
 
-fn bin_system(
-    mut commands: Commands,
-    q_bin: Query<(&Transform, &TriggerBox), With<Bin>>,
-    mut q_player: Query<(Entity, &Transform, &CollisionBox, &mut Inventory)>,
+fn seed_text(seed: Seed, daily: Seed, entry: &SeedEntry) -> String {
+    match &entry.0 {
+        Some(digits) => format!("Seed: {digits}_"),
+        None if seed == daily => format!("Seed: {} (daily)", seed.0),
+        None => format!("Seed: {}", seed.0),
+    }
+}
+
+/// Types in a seed after clicking it: Enter to use it, Escape to leave it as it was
+fn seed_entry_system(
     keyboard_input: Res<Input<KeyCode>>,
-    asset_server: Res<AssetServer>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut shift: NextShift,
 ) {
-    let (player, player_trans, player_box, mut inventory) =
-        q_player.get_single_mut().expect("Always a player");
-
-    let (bin_trans, bin_box) = q_bin.get_single().expect("Always a bin");
-
-    //info!("checking collisions");
-    let collision = collide(
-        player_trans.translation,
-        player_box.0.truncate(),
-        bin_trans.translation,
-        bin_box.0.truncate(),
-    );
+    let Some(digits) = &mut shift.seed_entry.0 else {
+        characters.clear();
+        return;
+    };
 
-    if collision.is_some() && keyboard_input.just_pressed(KeyCode::Down) {
-        for item in inventory.items.iter_mut() {
-            if let Some(ing) = item {
-                spawn_ingredient(&asset_server, &mut commands, ing.clone());
-            }
-            *item = None;
+    for event in characters.iter() {
+        if event.char.is_ascii_digit() && digits.len() < MAX_SEED_DIGITS {
+            digits.push(event.char);
         }
-        inventory.cake = None;
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        digits.pop();
+    }
 
-        commands.entity(player).despawn_descendants();
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        shift.finish_seed_entry();
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        shift.seed_entry.0 = None;
     }
 }
 
-/// Spawn a new bomb in set intervals of time
-fn level_timer_system(
-    time: Res<Time>,
-    mut timer: ResMut<LevelTimer>,
-    mut app_state: ResMut<NextState<GameState>>,
+fn update_seed_text(
+    seed: Res<Seed>,
+    daily: Res<DailySeed>,
+    seed_entry: Res<SeedEntry>,
+    mut query: Query<&mut Text, With<SeedText>>,
 ) {
-    // tick the timer
-    timer.0.tick(time.delta());
+    if !seed.is_changed() && !seed_entry.is_changed() {
+        return;
+    }
 
-    if timer.0.finished() {
-        app_state.set(GameState::EndScreen);
+    for mut text in query.iter_mut() {
+        text.sections[0].value = seed_text(*seed, daily.0, &seed_entry);
     }
 }
 
-// MAIN MENU
+// CONTROLS
+
+const SETTINGS_SAVE: &str = "settings";
+
+/// Player preferences, kept between runs
+#[derive(Resource, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Settings {
+    bindings: Bindings,
+    /// Stations wait for the interact button instead of acting on touch
+    interact_mode: bool,
+}
+
+impl Settings {
+    fn load() -> Self {
+        let mut settings: Settings = save::load(SETTINGS_SAVE);
+        settings.bindings.add_missing();
+        settings
+    }
+}
+
+/// Action and slot waiting for a new key or button in the controls menu
+#[derive(Resource, Default)]
+struct Rebinding(Option<(Action, usize)>);
 
 #[derive(Component)]
-struct TitleMenu;
+struct ControlsMenu;
 
 #[derive(Component)]
-enum TitleMenuAction {
-    NewGame,
-    Quit,
+struct BindingText(Action, usize);
+
+#[derive(Component)]
+struct InteractModeText;
+
+fn interact_mode_text(settings: &Settings) -> String {
+    let mode = if settings.interact_mode {
+        format!("press {}", settings.bindings.primary(Action::Interact))
+    } else {
+        "touch".to_string()
+    };
+    format!("Use stations by: {mode}")
 }
 
-fn setup_title_menu(mut commands: Commands) {
+fn setup_controls_menu(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    rebinding.0 = None;
+
     // Common style for all buttons on the screen
     let button_style = Style {
-        width: Val::Px(250.0),
-        height: Val::Px(65.0),
-        margin: UiRect::all(Val::Px(20.0)),
+        width: Val::Px(600.0),
+        height: Val::Px(45.0),
+        margin: UiRect::all(Val::Px(5.0)),
         justify_content: JustifyContent::Center,
         align_items: AlignItems::Center,
         ..default()
     };
 
     let button_text_style = TextStyle {
-        font_size: 40.0,
+        font_size: 25.0,
         color: Color::WHITE,
         ..default()
     };
@@ -1052,38 +3356,9 @@ fn setup_title_menu(mut commands: Commands) {
                 },
                 ..default()
             },
-            TitleMenu,
+            ControlsMenu,
         ))
         .with_children(|parent| {
-            parent
-                .spawn(NodeBundle {
-                    style: Style {
-                        width: Val::Percent(100.0),
-                        height: Val::Px(40.0),
-			position_type: PositionType::Absolute,
-			bottom: Val::Px(0.0),
-                        ..default()
-                    },
-                    background_color: Color::CRIMSON.into(),
-                    ..default()
-                })
-                .with_children(|parent| {
-                    parent.spawn((
-                        TextBundle::from_section(r#"    Music "Paris" by Sakura Girl (https://officialsakuragirl.wixsite.com/official)"#,
-                            TextStyle {
-                                font_size: 20.0,
-                                color: Color::WHITE,
-                                ..default()
-                            },
-                        )
-                        .with_style(Style { ..default() }),
-                        // Because this is a distinct label widget and
-                        // not button/list item text, this is necessary
-                        // for accessibility to treat the text accordingly.
-                        Label,
-                    ));
-                });
-
             parent
                 .spawn(NodeBundle {
                     style: Style {
@@ -1097,9 +3372,9 @@ fn setup_title_menu(mut commands: Commands) {
                 .with_children(|parent| {
                     parent.spawn((
                         TextBundle::from_section(
-                            "Truly Teeny Tiny Bakery",
+                            "Controls\nClick a slot, then press a key or a gamepad button",
                             TextStyle {
-                                font_size: 60.0,
+                                font_size: 30.0,
                                 color: Color::WHITE,
                                 ..default()
                             },
@@ -1116,21 +3391,56 @@ fn setup_title_menu(mut commands: Commands) {
                         Label,
                     ));
 
-                    parent
-                        .spawn((
-                            ButtonBundle {
-                                style: button_style.clone(),
-                                background_color: NORMAL_BUTTON.into(),
+                    for action in Action::ALL {
+                        parent
+                            .spawn(NodeBundle {
+                                style: Style {
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
                                 ..default()
-                            },
-                            TitleMenuAction::NewGame,
-                        ))
-                        .with_children(|parent| {
-                            parent.spawn(TextBundle::from_section(
-                                "New Game",
-                                button_text_style.clone(),
-                            ));
-                        });
+                            })
+                            .with_children(|parent| {
+                                parent.spawn((
+                                    TextBundle::from_section(
+                                        format!("{action:?}"),
+                                        button_text_style.clone(),
+                                    )
+                                    .with_style(Style {
+                                        width: Val::Px(150.0),
+                                        ..default()
+                                    }),
+                                    // Because this is a distinct label widget and
+                                    // not button/list item text, this is necessary
+                                    // for accessibility to treat the text accordingly.
+                                    Label,
+                                ));
+                                for slot in 0..BINDING_SLOTS {
+                                    parent
+                                        .spawn((
+                                            ButtonBundle {
+                                                style: Style {
+                                                    width: Val::Px(200.0),
+                                                    ..button_style.clone()
+                                                },
+                                                background_color: NORMAL_BUTTON.into(),
+                                                ..default()
+                                            },
+                                            TitleMenuAction::Rebind(action, slot),
+                                        ))
+                                        .with_children(|parent| {
+                                            parent.spawn((
+                                                TextBundle::from_section(
+                                                    "",
+                                                    button_text_style.clone(),
+                                                ),
+                                                BindingText(action, slot),
+                                            ));
+                                        });
+                                }
+                            });
+                    }
+
                     parent
                         .spawn((
                             ButtonBundle {
@@ -1138,21 +3448,169 @@ fn setup_title_menu(mut commands: Commands) {
                                 background_color: NORMAL_BUTTON.into(),
                                 ..default()
                             },
-                            TitleMenuAction::Quit,
+                            TitleMenuAction::ToggleInteractMode,
                         ))
                         .with_children(|parent| {
-                            parent
-                                .spawn(TextBundle::from_section("Quit", button_text_style.clone()));
+                            parent.spawn((
+                                TextBundle::from_section(
+                                    interact_mode_text(&settings),
+                                    button_text_style.clone(),
+                                ),
+                                InteractModeText,
+                            ));
                         });
 
+                    for (action, text) in [
+                        (TitleMenuAction::ResetControls, "Reset to defaults"),
+                        (TitleMenuAction::MainMenu, "Back"),
+                    ] {
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: button_style.clone(),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                action,
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    text,
+                                    button_text_style.clone(),
+                                ));
+                            });
+                    }
+                });
+        });
+}
+
+/// Binds the next key or gamepad button pressed to the action being rebound
+fn rebind_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut settings: ResMut<Settings>,
+) {
+    let Some((action, slot)) = rebinding.0 else {
+        return;
+    };
+    let bindings = &mut settings.bindings;
 
-		                        parent.spawn((
+    let bound = if let Some(&key) = keyboard_input.get_just_pressed().next() {
+        if key == KeyCode::Escape && action != Action::Pause {
+            rebinding.0 = None;
+            return;
+        }
+        bindings.bind_key(action, slot, key)
+    } else if let Some(button) = gamepad_buttons.get_just_pressed().next() {
+        bindings.bind_button(action, slot, button.button_type)
+    } else {
+        return;
+    };
+
+    rebinding.0 = None;
+    if bound {
+        save::save(SETTINGS_SAVE, &*settings);
+    } else {
+        warn!("Can't rebind {action:?}, it would leave another action without input");
+    }
+}
+
+fn update_binding_text(
+    settings: Res<Settings>,
+    rebinding: Res<Rebinding>,
+    mut q_text: Query<(&mut Text, &BindingText)>,
+    mut q_mode_text: Query<&mut Text, (With<InteractModeText>, Without<BindingText>)>,
+) {
+    if !settings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+
+    for mut text in q_mode_text.iter_mut() {
+        text.sections[0].value = interact_mode_text(&settings);
+    }
+
+    for (mut text, &BindingText(action, slot)) in q_text.iter_mut() {
+        text.sections[0].value = if rebinding.0 == Some((action, slot)) {
+            "press a key...".to_string()
+        } else {
+            settings.bindings.describe_slot(action, slot)
+        };
+    }
+}
+
+// PAUSE MENU
+
+fn pause_system(
+    actions: Res<ActionState>,
+    state: Res<State<PauseState>>,
+    mut next_state: ResMut<NextState<PauseState>>,
+) {
+    if actions.just_pressed(Action::Pause) {
+        next_state.set(match state.get() {
+            PauseState::Running => PauseState::Paused,
+            PauseState::Paused => PauseState::Running,
+        });
+    }
+}
+
+fn restart_system(mut app_state: ResMut<NextState<GameState>>) {
+    app_state.set(GameState::InGame);
+}
+
+#[derive(Component)]
+struct PauseMenu;
+
+fn setup_pause_menu(mut commands: Commands) {
+    // Common style for all buttons on the screen
+    let button_style = Style {
+        width: Val::Px(300.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: Color::WHITE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Vw(100.0),
+                    height: Val::Vh(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    position_type: PositionType::Absolute,
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                ..default()
+            },
+            PauseMenu,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::CRIMSON.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
                         TextBundle::from_section(
-                            "Left-Right arrows to move\n
-Up arrow to jump\n
-Down arrow on bin to clear inventory",
+                            "Paused",
                             TextStyle {
-                                font_size: 30.0,
+                                font_size: 60.0,
                                 color: Color::WHITE,
                                 ..default()
                             },
@@ -1168,54 +3626,71 @@ Down arrow on bin to clear inventory",
                         // for accessibility to treat the text accordingly.
                         Label,
                     ));
+
+                    for (action, text) in [
+                        (TitleMenuAction::Resume, "Resume"),
+                        (TitleMenuAction::Restart, "Restart"),
+                        (TitleMenuAction::MainMenu, "Quit to title"),
+                    ] {
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: button_style.clone(),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                action,
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    text,
+                                    button_text_style.clone(),
+                                ));
+                            });
+                    }
                 });
         });
 }
 
-fn title_menu(keyboard_input: Res<Input<KeyCode>>, mut app_state: ResMut<NextState<GameState>>) {}
-
-fn title_menu_action(
-    interaction_query: Query<
-        (&Interaction, &TitleMenuAction),
-        (Changed<Interaction>, With<Button>),
-    >,
-    mut app_exit_events: EventWriter<AppExit>,
-    mut app_state: ResMut<NextState<GameState>>,
-) {
-    for (interaction, menu_button_action) in &interaction_query {
-        if *interaction == Interaction::Pressed {
-            match menu_button_action {
-                TitleMenuAction::NewGame => app_state.set(GameState::InGame),
-                TitleMenuAction::Quit => app_exit_events.send(AppExit),
-            }
-        }
-    }
-}
-
-const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
-const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
-const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
-
-fn button_system(
-    mut interaction_query: Query<
-        (&Interaction, &mut BackgroundColor),
-        (Changed<Interaction>, With<Button>),
-    >,
-) {
-    for (interaction, mut color) in &mut interaction_query {
-        *color = match *interaction {
-            Interaction::Pressed => PRESSED_BUTTON.into(),
-            Interaction::Hovered => HOVERED_BUTTON.into(),
-            Interaction::None => NORMAL_BUTTON.into(),
-        }
-    }
-}
-
 #[derive(Component)]
 struct EndScreen;
 
-fn setup_end_screen(mut commands: Commands, score: Res<Score>) {
+#[allow(clippy::too_many_arguments)]
+fn setup_end_screen(
+    In(new_record): In<bool>,
+    mut commands: Commands,
+    score: Res<Score>,
+    missed: Res<MissedCustomers>,
+    level: Res<CurrentLevel>,
+    selected: Res<SelectedLevel>,
+    campaign: Res<Campaign>,
+    seed: Res<Seed>,
+) {
     let score = score.0;
+    let seed = seed.0;
+    let missed = missed.0;
+    let stars = level.0.stars_for(score);
+    let (headline, goal) = if stars > 0 {
+        (
+            "Well done!",
+            format!("Goal reached! {}", format_stars(stars)),
+        )
+    } else {
+        (
+            "Not quite...",
+            format!("Sell {} cakes to clear {}", level.0.goal(), level.0.name),
+        )
+    };
+    let high_score = if new_record {
+        "New record!".to_string()
+    } else {
+        let best = campaign
+            .get(selected.0)
+            .map_or(0, |record| record.high_score);
+        format!("Best: {best} cakes")
+    };
+    let next_level = selected.0 + 1;
+    let has_next_level = next_level < LEVELS.len() && campaign.is_unlocked(next_level);
 
     // Common style for all buttons on the screen
     let button_style = Style {
@@ -1262,7 +3737,9 @@ fn setup_end_screen(mut commands: Commands, score: Res<Score>) {
                 .with_children(|parent| {
                     parent.spawn((
                         TextBundle::from_section(
-                            format!("Well done!\nYou sold {score} cakes!"),
+                            format!(
+                                "{headline}\nYou sold {score} cakes!\n{high_score}\n{missed} customers left empty-handed\n{goal}\nSeed: {seed}"
+                            ),
                             TextStyle {
                                 font_size: 60.0,
                                 color: Color::WHITE,
@@ -1292,7 +3769,39 @@ fn setup_end_screen(mut commands: Commands, score: Res<Score>) {
                         ))
                         .with_children(|parent| {
                             parent.spawn(TextBundle::from_section(
-                                "New Game",
+                                "Retry",
+                                button_text_style.clone(),
+                            ));
+                        });
+                    if has_next_level {
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: button_style.clone(),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                TitleMenuAction::NextLevel,
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    "Next level",
+                                    button_text_style.clone(),
+                                ));
+                            });
+                    }
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            TitleMenuAction::LevelSelect,
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(
+                                "Levels",
                                 button_text_style.clone(),
                             ));
                         });
@@ -0,0 +1,599 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::RangeInclusive,
+    str::FromStr,
+    time::Duration,
+};
+
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    reflect::{TypePath, TypeUuid},
+};
+use serde::Deserialize;
+
+use crate::{CakeType, IngredientType, IntermediateType, Item};
+
+/// How many items a recipe can ask for
+pub const RECIPE_SIZE: RangeInclusive<usize> = 2..=6;
+
+/// Kinds of cooking tables a recipe step can need
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum StationType {
+    Mixer,
+    Oven,
+    DecoratingTable,
+}
+
+impl StationType {
+    /// What doing a step here is called, for prompts
+    pub fn verb(self) -> &'static str {
+        match self {
+            StationType::Mixer => "Mix",
+            StationType::Oven => "Bake",
+            StationType::DecoratingTable => "Decorate",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StationType::Mixer => "Mixer",
+            StationType::Oven => "Oven",
+            StationType::DecoratingTable => "Decorating table",
+        }
+    }
+
+    /// How long a step takes at this station
+    pub fn duration(self) -> Duration {
+        match self {
+            StationType::Mixer => Duration::from_secs(4),
+            StationType::Oven => Duration::from_secs(8),
+            StationType::DecoratingTable => Duration::from_secs(5),
+        }
+    }
+
+    /// Whether what's done here burns when left too long
+    pub fn burns(self) -> bool {
+        self == StationType::Oven
+    }
+}
+
+/// Items used by a recipe, as a multiset: order doesn't matter, duplicates do
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Recipe {
+    // Kept sorted so equal multisets compare (and hash) equal
+    ingredients: Vec<Item>,
+}
+
+impl Recipe {
+    pub fn new(ingredients: &[Item]) -> Self {
+        let mut ingredients = Vec::from(ingredients);
+        ingredients.sort();
+        Self { ingredients }
+    }
+
+    pub fn ingredients(&self) -> &[Item] {
+        &self.ingredients
+    }
+
+    pub fn len(&self) -> usize {
+        self.ingredients.len()
+    }
+
+    /// Whether `items` hold every ingredient of the recipe, as many times as needed
+    pub fn is_satisfied_by(&self, items: &[Item]) -> bool {
+        let mut available = Vec::from(items);
+        self.ingredients
+            .iter()
+            .all(|ing| match available.iter().position(|item| item == ing) {
+                Some(index) => {
+                    available.swap_remove(index);
+                    true
+                }
+                None => false,
+            })
+    }
+}
+
+/// Something a station can start making
+#[derive(Debug, Clone, Copy)]
+pub enum Craft<'a> {
+    Intermediate(&'a IntermediateRecipe),
+    Cake(&'a CakeRecipe),
+}
+
+impl<'a> Craft<'a> {
+    pub fn name(self) -> &'a str {
+        match self {
+            Craft::Intermediate(intermediate) => &intermediate.name,
+            Craft::Cake(cake) => &cake.name,
+        }
+    }
+
+    pub fn recipe(self) -> &'a Recipe {
+        match self {
+            Craft::Intermediate(intermediate) => &intermediate.recipe,
+            Craft::Cake(cake) => &cake.recipe,
+        }
+    }
+
+    fn station(self) -> StationType {
+        match self {
+            Craft::Intermediate(intermediate) => intermediate.station,
+            Craft::Cake(cake) => cake.steps[0],
+        }
+    }
+}
+
+/// Finds what to start making with `items` at a `station`.
+///
+/// Leftover items are allowed, so several recipes can match: the one using
+/// the most items wins, and on a tie the one listed first in the book,
+/// intermediates before cakes.
+pub fn find_recipe<'a>(
+    book: &'a RecipeBook,
+    items: &[Item],
+    station: StationType,
+) -> Option<Craft<'a>> {
+    let intermediates = book.intermediates.iter().map(Craft::Intermediate);
+    let cakes = book.cakes.iter().map(Craft::Cake);
+
+    // max_by_key keeps the last maximum, so walk the book backwards
+    intermediates
+        .chain(cakes)
+        .rev()
+        .filter(|craft| craft.station() == station && craft.recipe().is_satisfied_by(items))
+        .max_by_key(|craft| craft.recipe().len())
+}
+
+/// Something made at a station only to go into another recipe, like batter
+#[derive(Debug, Clone)]
+pub struct IntermediateRecipe {
+    pub product: IntermediateType,
+    pub name: String,
+    pub sprite: String,
+    pub recipe: Recipe,
+    pub station: StationType,
+}
+
+#[derive(Debug, Clone)]
+pub struct CakeRecipe {
+    pub cake: CakeType,
+    pub name: String,
+    pub sprite: String,
+    pub recipe: Recipe,
+    /// Stations to go through in order, the ingredients are used at the first one
+    pub steps: Vec<StationType>,
+}
+
+/// All the cakes the bakery knows about, loaded from a `.recipes.ron` file
+#[derive(Debug, Clone, Default, TypeUuid, TypePath)]
+#[uuid = "5d3e1f0c-6b7a-4c2e-9f3d-8a1b2c4d6e70"]
+pub struct RecipeBook {
+    pub intermediates: Vec<IntermediateRecipe>,
+    pub cakes: Vec<CakeRecipe>,
+}
+
+impl RecipeBook {
+    pub fn cake(&self, cake: &CakeType) -> Option<&CakeRecipe> {
+        self.cakes.iter().find(|recipe| recipe.cake == *cake)
+    }
+
+    pub fn intermediate(&self, product: &IntermediateType) -> Option<&IntermediateRecipe> {
+        self.intermediates
+            .iter()
+            .find(|recipe| recipe.product == *product)
+    }
+
+    /// Every station a cake goes through, including for its intermediates
+    pub fn stations_for(&self, cake: &CakeRecipe) -> Vec<StationType> {
+        let mut stations = cake.steps.clone();
+        let mut pending: Vec<&Recipe> = vec![&cake.recipe];
+        while let Some(recipe) = pending.pop() {
+            for item in recipe.ingredients() {
+                let Item::Intermediate(product) = item else {
+                    continue;
+                };
+                if let Some(intermediate) = self.intermediate(product) {
+                    stations.push(intermediate.station);
+                    pending.push(&intermediate.recipe);
+                }
+            }
+        }
+        stations
+    }
+}
+
+// What the designers write, before validation
+#[derive(Deserialize)]
+struct RawRecipeBook {
+    #[serde(default)]
+    intermediates: Vec<RawIntermediate>,
+    recipes: Vec<RawRecipe>,
+}
+
+#[derive(Deserialize)]
+struct RawIntermediate {
+    id: String,
+    name: String,
+    sprite: String,
+    ingredients: Vec<String>,
+    station: StationType,
+}
+
+#[derive(Deserialize)]
+struct RawRecipe {
+    id: String,
+    name: String,
+    sprite: String,
+    ingredients: Vec<String>,
+    #[serde(default = "default_steps")]
+    steps: Vec<StationType>,
+}
+
+fn default_steps() -> Vec<StationType> {
+    vec![StationType::Oven]
+}
+
+#[derive(Debug)]
+pub enum RecipeBookError {
+    Parse(ron::error::SpannedError),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for RecipeBookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecipeBookError::Parse(err) => write!(f, "could not parse recipe book: {err}"),
+            RecipeBookError::Invalid(problems) => {
+                write!(f, "invalid recipe book:")?;
+                for problem in problems {
+                    write!(f, "\n  - {problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecipeBookError {}
+
+impl RecipeBook {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RecipeBookError> {
+        let raw: RawRecipeBook = ron::de::from_bytes(bytes).map_err(RecipeBookError::Parse)?;
+
+        let mut problems = Vec::new();
+        let mut intermediates = Vec::new();
+        let mut cakes = Vec::new();
+        // Recipes made at the same station from the same items can't be told apart
+        let mut seen_recipes = HashMap::new();
+
+        let mut ids = HashSet::new();
+        for (index, raw) in raw.intermediates.into_iter().enumerate() {
+            let what = format!("intermediate #{} ({:?})", index + 1, raw.id);
+            let before = problems.len();
+
+            check_id(&raw.id, &mut ids, &what, &mut problems);
+            if IngredientType::from_str(&raw.id).is_ok() {
+                problems.push(format!("{what}: id is already an ingredient"));
+            }
+            if raw.sprite.is_empty() {
+                problems.push(format!("{what}: missing sprite"));
+            }
+            // Only earlier intermediates can be used, so there are no loops
+            let recipe = parse_ingredients(&raw.ingredients, &intermediates, &what, &mut problems);
+
+            if problems.len() == before {
+                if let Some(other) = seen_recipes.get(&(recipe.clone(), raw.station)) {
+                    problems.push(format!("{what}: same ingredients and station as {other}"));
+                }
+            }
+
+            if problems.len() == before {
+                seen_recipes.insert((recipe.clone(), raw.station), what);
+                intermediates.push(IntermediateRecipe {
+                    product: IntermediateType(raw.id),
+                    name: raw.name,
+                    sprite: raw.sprite,
+                    recipe,
+                    station: raw.station,
+                });
+            }
+        }
+
+        let mut ids = HashSet::new();
+        for (index, raw) in raw.recipes.into_iter().enumerate() {
+            let what = format!("recipe #{} ({:?})", index + 1, raw.id);
+            let before = problems.len();
+
+            check_id(&raw.id, &mut ids, &what, &mut problems);
+            if raw.sprite.is_empty() {
+                problems.push(format!("{what}: missing sprite"));
+            }
+            if raw.steps.is_empty() {
+                problems.push(format!("{what}: no steps"));
+            }
+            let recipe = parse_ingredients(&raw.ingredients, &intermediates, &what, &mut problems);
+
+            if problems.len() == before {
+                if let Some(other) = seen_recipes.get(&(recipe.clone(), raw.steps[0])) {
+                    problems.push(format!("{what}: same ingredients and station as {other}"));
+                }
+            }
+
+            if problems.len() == before {
+                seen_recipes.insert((recipe.clone(), raw.steps[0]), what);
+                cakes.push(CakeRecipe {
+                    cake: CakeType(raw.id),
+                    name: raw.name,
+                    sprite: raw.sprite,
+                    recipe,
+                    steps: raw.steps,
+                });
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(Self {
+                intermediates,
+                cakes,
+            })
+        } else {
+            Err(RecipeBookError::Invalid(problems))
+        }
+    }
+}
+
+fn check_id(id: &str, ids: &mut HashSet<String>, what: &str, problems: &mut Vec<String>) {
+    if id.is_empty() {
+        problems.push(format!("{what}: empty id"));
+    } else if !ids.insert(id.to_string()) {
+        problems.push(format!("{what}: duplicate id"));
+    }
+}
+
+/// Reads ingredient names, which can also be the id of an intermediate
+fn parse_ingredients(
+    names: &[String],
+    intermediates: &[IntermediateRecipe],
+    what: &str,
+    problems: &mut Vec<String>,
+) -> Recipe {
+    if !RECIPE_SIZE.contains(&names.len()) {
+        problems.push(format!(
+            "{what}: needs {} to {} ingredients, got {}",
+            RECIPE_SIZE.start(),
+            RECIPE_SIZE.end(),
+            names.len()
+        ));
+    }
+
+    let mut items = Vec::new();
+    for name in names.iter() {
+        if let Ok(ing) = IngredientType::from_str(name) {
+            items.push(Item::Ingredient(ing));
+        } else if let Some(intermediate) = intermediates
+            .iter()
+            .find(|intermediate| intermediate.product.0 == *name)
+        {
+            items.push(Item::Intermediate(intermediate.product.clone()));
+        } else {
+            problems.push(format!("{what}: unknown ingredient {name:?}"));
+        }
+    }
+    Recipe::new(&items)
+}
+
+#[derive(Default)]
+pub struct RecipeBookLoader;
+
+impl AssetLoader for RecipeBookLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let book = RecipeBook::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(book));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["recipes.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ing(ingredient: IngredientType) -> Item {
+        Item::Ingredient(ingredient)
+    }
+
+    fn book(ron: &str) -> RecipeBook {
+        RecipeBook::from_bytes(ron.as_bytes()).expect("Test book is valid")
+    }
+
+    fn problems(ron: &str) -> Vec<String> {
+        match RecipeBook::from_bytes(ron.as_bytes()) {
+            Err(RecipeBookError::Invalid(problems)) => problems,
+            Err(err) => panic!("expected validation problems, got {err}"),
+            Ok(_) => panic!("expected validation problems, got a valid book"),
+        }
+    }
+
+    fn found<'a>(book: &'a RecipeBook, items: &[Item], station: StationType) -> Option<&'a str> {
+        find_recipe(book, items, station).map(Craft::name)
+    }
+
+    const CHOCOLATE: &str = r#"(
+        recipes: [
+            (id: "plain", name: "Plain", sprite: "plain.png", ingredients: ["Eggs", "Flour"]),
+            (id: "chocolate", name: "Chocolate", sprite: "choc.png",
+                ingredients: ["Eggs", "Flour", "Chocolate"]),
+            (id: "omelette", name: "Omelette", sprite: "omelette.png",
+                ingredients: ["Eggs", "Milk"]),
+            (id: "frosted", name: "Frosted", sprite: "frosted.png",
+                ingredients: ["Flour", "Milk"], steps: [DecoratingTable]),
+        ],
+    )"#;
+
+    #[test]
+    fn duplicates_must_all_be_there() {
+        use IngredientType::*;
+        let recipe = Recipe::new(&[ing(Chocolate), ing(Flour), ing(Chocolate)]);
+
+        assert!(!recipe.is_satisfied_by(&[ing(Chocolate), ing(Flour)]));
+        assert!(recipe.is_satisfied_by(&[ing(Chocolate), ing(Flour), ing(Chocolate)]));
+        assert!(recipe.is_satisfied_by(&[ing(Chocolate), ing(Eggs), ing(Chocolate), ing(Flour)]));
+    }
+
+    #[test]
+    fn order_does_not_matter() {
+        use IngredientType::*;
+        assert_eq!(
+            Recipe::new(&[ing(Eggs), ing(Flour), ing(Eggs)]),
+            Recipe::new(&[ing(Flour), ing(Eggs), ing(Eggs)])
+        );
+    }
+
+    #[test]
+    fn recipe_using_most_items_wins() {
+        use IngredientType::*;
+        let book = book(CHOCOLATE);
+
+        let items = [ing(Flour), ing(Chocolate), ing(Eggs)];
+        assert_eq!(found(&book, &items, StationType::Oven), Some("Chocolate"));
+        let items = [ing(Flour), ing(Eggs)];
+        assert_eq!(found(&book, &items, StationType::Oven), Some("Plain"));
+    }
+
+    #[test]
+    fn first_listed_wins_a_tie() {
+        use IngredientType::*;
+        let book = book(CHOCOLATE);
+
+        // Plain and omelette both use two of these
+        let items = [ing(Milk), ing(Eggs), ing(Flour)];
+        assert_eq!(found(&book, &items, StationType::Oven), Some("Plain"));
+    }
+
+    #[test]
+    fn only_recipes_for_the_station() {
+        use IngredientType::*;
+        let book = book(CHOCOLATE);
+
+        let items = [ing(Flour), ing(Milk)];
+        assert_eq!(found(&book, &items, StationType::Oven), None);
+        assert_eq!(
+            found(&book, &items, StationType::DecoratingTable),
+            Some("Frosted")
+        );
+        assert_eq!(found(&book, &items, StationType::Mixer), None);
+    }
+
+    #[test]
+    fn no_match() {
+        use IngredientType::*;
+        let book = book(CHOCOLATE);
+
+        assert_eq!(found(&book, &[], StationType::Oven), None);
+        assert_eq!(found(&book, &[ing(Eggs)], StationType::Oven), None);
+        assert_eq!(
+            found(&book, &[ing(Carrot), ing(Strawberry)], StationType::Oven),
+            None
+        );
+    }
+
+    #[test]
+    fn intermediates_go_into_cakes() {
+        use IngredientType::*;
+        let book = book(
+            r#"(
+                intermediates: [
+                    (id: "batter", name: "Batter", sprite: "batter.png",
+                        ingredients: ["Eggs", "Flour"], station: Mixer),
+                ],
+                recipes: [
+                    (id: "sponge", name: "Sponge", sprite: "sponge.png",
+                        ingredients: ["batter", "Milk"]),
+                ],
+            )"#,
+        );
+
+        let items = [ing(Eggs), ing(Flour)];
+        assert_eq!(found(&book, &items, StationType::Mixer), Some("Batter"));
+        let items = [
+            Item::Intermediate(IntermediateType("batter".to_string())),
+            ing(Milk),
+        ];
+        assert_eq!(found(&book, &items, StationType::Oven), Some("Sponge"));
+
+        let sponge = book.cake(&CakeType("sponge".to_string())).unwrap();
+        assert_eq!(
+            book.stations_for(sponge),
+            vec![StationType::Oven, StationType::Mixer]
+        );
+    }
+
+    #[test]
+    fn validation_lists_every_problem() {
+        let problems = problems(
+            r#"(
+                recipes: [
+                    (id: "plain", name: "Plain", sprite: "a.png", ingredients: ["Eggs", "Flour"]),
+                    (id: "", name: "Nameless", sprite: "b.png", ingredients: ["Eggs", "Milk"]),
+                    (id: "twice", name: "Twice", sprite: "", ingredients: ["Eggs", "Carrot"]),
+                    (id: "twice", name: "Again", sprite: "c.png", ingredients: ["Milk", "Carrot"]),
+                    (id: "lonely", name: "Lonely", sprite: "d.png", ingredients: ["Eggs"]),
+                    (id: "mystery", name: "Mystery", sprite: "e.png",
+                        ingredients: ["Eggs", "Unicorn"]),
+                    (id: "stepless", name: "Stepless", sprite: "f.png",
+                        ingredients: ["Milk", "Flour"], steps: []),
+                    (id: "copy", name: "Copy", sprite: "g.png", ingredients: ["Flour", "Eggs"]),
+                ],
+            )"#,
+        );
+
+        assert_eq!(
+            problems,
+            vec![
+                r#"recipe #2 (""): empty id"#,
+                r#"recipe #3 ("twice"): missing sprite"#,
+                r#"recipe #4 ("twice"): duplicate id"#,
+                r#"recipe #5 ("lonely"): needs 2 to 6 ingredients, got 1"#,
+                r#"recipe #6 ("mystery"): unknown ingredient "Unicorn""#,
+                r#"recipe #7 ("stepless"): no steps"#,
+                r#"recipe #8 ("copy"): same ingredients and station as recipe #1 ("plain")"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn intermediates_only_use_earlier_ones() {
+        let problems = problems(
+            r#"(
+                intermediates: [
+                    (id: "dough", name: "Dough", sprite: "a.png",
+                        ingredients: ["batter", "Flour"], station: Mixer),
+                    (id: "batter", name: "Batter", sprite: "b.png",
+                        ingredients: ["Eggs", "Flour"], station: Mixer),
+                    (id: "Eggs", name: "Eggs", sprite: "c.png",
+                        ingredients: ["Milk", "Flour"], station: Mixer),
+                ],
+                recipes: [],
+            )"#,
+        );
+
+        assert_eq!(
+            problems,
+            vec![
+                r#"intermediate #1 ("dough"): unknown ingredient "batter""#,
+                r#"intermediate #3 ("Eggs"): id is already an ingredient"#,
+            ]
+        );
+    }
+}
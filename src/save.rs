@@ -0,0 +1,73 @@
+//! Keeps small bits of state between runs: RON files in `save/` on native,
+//! `localStorage` entries in the browser.
+
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Loads `name`, falling back to the default if it was never saved or can't be read
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> T {
+    let Some(contents) = read(name) else {
+        return T::default();
+    };
+
+    match ron::from_str(&contents) {
+        Ok(value) => value,
+        Err(err) => {
+            warn!("Ignoring unreadable save {name}: {err}");
+            T::default()
+        }
+    }
+}
+
+pub fn save<T: Serialize>(name: &str, value: &T) {
+    let result = ron::ser::to_string_pretty(value, PrettyConfig::default())
+        .map_err(|err| err.to_string())
+        .and_then(|contents| write(name, &contents));
+
+    if let Err(err) = result {
+        error!("Could not save {name}: {err}");
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn path(name: &str) -> std::path::PathBuf {
+    std::path::Path::new("save").join(format!("{name}.ron"))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read(name: &str) -> Option<String> {
+    std::fs::read_to_string(path(name)).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write(name: &str, contents: &str) -> Result<(), String> {
+    let path = path(name);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    std::fs::write(path, contents).map_err(|err| err.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn key(name: &str) -> String {
+    format!("ld54.{name}")
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read(name: &str) -> Option<String> {
+    storage()?.get_item(&key(name)).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write(name: &str, contents: &str) -> Result<(), String> {
+    storage()
+        .ok_or("localStorage is not available")?
+        .set_item(&key(name), contents)
+        .map_err(|err| format!("{err:?}"))
+}